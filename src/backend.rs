@@ -0,0 +1,212 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use crossterm::{
+    cursor::MoveTo,
+    queue,
+    style::{Color, Print, SetBackgroundColor, SetForegroundColor},
+};
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::screen::{Cell, BLANK, WIDE_TAIL};
+
+//Where the frames built in a `Screen` end up
+pub trait Backend {
+    //Paints a single cell, only called for cells that changed since the last frame
+    fn draw_cell(self: &mut Self, column: u16, row: u16, cell: Cell) -> std::io::Result<()>;
+    fn set_cursor(self: &mut Self, column: u16, row: u16) -> std::io::Result<()>;
+    //Ends the frame
+    fn flush(self: &mut Self) -> std::io::Result<()>;
+
+    //The terminal takes care of this itself
+    fn resize(self: &mut Self, _width: u16, _height: u16) {}
+
+    //Hands `text` to the system clipboard
+    fn set_clipboard(self: &mut Self, text: &str) -> std::io::Result<()>;
+}
+
+//Draws on the terminal through crossterm
+pub struct CrosstermBackend<W: Write> {
+    out: W,
+    //Where the terminal cursor ends up after the last print, saves a `MoveTo` for runs of cells
+    position: Option<(u16, u16)>,
+    colors: Option<(Color, Color)>,
+}
+
+impl<W: Write> CrosstermBackend<W> {
+    pub fn new(out: W) -> Self {
+        CrosstermBackend {
+            out,
+            position: None,
+            colors: None,
+        }
+    }
+}
+
+impl<W: Write> Backend for CrosstermBackend<W> {
+    fn draw_cell(self: &mut Self, column: u16, row: u16, cell: Cell) -> std::io::Result<()> {
+        //Already painted by the wide char to its left
+        if cell.ch == WIDE_TAIL {
+            return Ok(());
+        }
+
+        if self.position != Some((column, row)) {
+            queue!(self.out, MoveTo(column, row))?;
+        }
+        if self.colors != Some((cell.fg, cell.bg)) {
+            queue!(
+                self.out,
+                SetForegroundColor(cell.fg),
+                SetBackgroundColor(cell.bg)
+            )?;
+            self.colors = Some((cell.fg, cell.bg));
+        }
+
+        queue!(self.out, Print(cell.ch))?;
+        //Zero width chars may or may not move the terminal cursor, better to place it again
+        self.position = match cell.ch.width() {
+            Some(width @ 1..=2) => Some((column + width as u16, row)),
+            _ => None,
+        };
+        Ok(())
+    }
+
+    fn set_cursor(self: &mut Self, column: u16, row: u16) -> std::io::Result<()> {
+        queue!(self.out, MoveTo(column, row))?;
+        self.position = Some((column, row));
+        Ok(())
+    }
+
+    //OSC 52, the terminal puts the text on the clipboard of the machine it runs on, even when
+    //the editor runs somewhere else over SSH
+    fn set_clipboard(self: &mut Self, text: &str) -> std::io::Result<()> {
+        queue!(self.out, Print(format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))))?;
+        self.out.flush()
+    }
+
+    fn flush(self: &mut Self) -> std::io::Result<()> {
+        //Anything may have been printed in between frames
+        self.position = None;
+        self.colors = None;
+        self.out.flush()
+    }
+}
+
+struct Frame {
+    width: u16,
+    cells: Vec<Cell>,
+    cursor: (u16, u16),
+    clipboard: Option<String>,
+}
+
+//Keeps the frames in memory instead, for running without a terminal. Clones share
+//the same frame, so one can be handed to the editor and another kept to look at it.
+#[derive(Clone)]
+pub struct MemoryBackend {
+    frame: Rc<RefCell<Frame>>,
+}
+
+impl MemoryBackend {
+    pub fn new(width: u16, height: u16) -> Self {
+        MemoryBackend {
+            frame: Rc::new(RefCell::new(Frame {
+                width,
+                cells: vec![BLANK; width as usize * height as usize],
+                cursor: (0, 0),
+                clipboard: None,
+            })),
+        }
+    }
+
+    //The text of `row` without trailing blanks
+    pub fn row_text(self: &Self, row: u16) -> String {
+        let frame = self.frame.borrow();
+        let start = row as usize * frame.width as usize;
+        let text: String = frame.cells[start..start + frame.width as usize]
+            .iter()
+            .map(|cell| cell.ch)
+            .filter(|ch| *ch != WIDE_TAIL)
+            .collect();
+
+        String::from(text.trim_end())
+    }
+
+    //Every row, one per line
+    pub fn text(self: &Self) -> String {
+        let rows = {
+            let frame = self.frame.borrow();
+            frame.cells.len() / frame.width as usize
+        };
+        (0..rows as u16)
+            .map(|row| self.row_text(row))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    pub fn cursor(self: &Self) -> (u16, u16) {
+        self.frame.borrow().cursor
+    }
+
+    #[cfg(test)]
+    pub fn clipboard(self: &Self) -> Option<String> {
+        self.frame.borrow().clipboard.clone()
+    }
+
+    #[cfg(test)]
+    pub fn cell(self: &Self, column: u16, row: u16) -> Cell {
+        let frame = self.frame.borrow();
+        frame.cells[row as usize * frame.width as usize + column as usize]
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn draw_cell(self: &mut Self, column: u16, row: u16, cell: Cell) -> std::io::Result<()> {
+        let mut frame = self.frame.borrow_mut();
+        let index = row as usize * frame.width as usize + column as usize;
+        if let Some(slot) = frame.cells.get_mut(index) {
+            *slot = cell;
+        }
+        Ok(())
+    }
+
+    fn set_cursor(self: &mut Self, column: u16, row: u16) -> std::io::Result<()> {
+        self.frame.borrow_mut().cursor = (column, row);
+        Ok(())
+    }
+
+    fn resize(self: &mut Self, width: u16, height: u16) {
+        let mut frame = self.frame.borrow_mut();
+        frame.width = width;
+        frame.cells = vec![BLANK; width as usize * height as usize];
+    }
+
+    fn set_clipboard(self: &mut Self, text: &str) -> std::io::Result<()> {
+        self.frame.borrow_mut().clipboard = Some(String::from(text));
+        Ok(())
+    }
+
+    fn flush(self: &mut Self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
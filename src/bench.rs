@@ -0,0 +1,89 @@
+#![allow(
+    dead_code,
+    clippy::needless_arbitrary_self_type,
+    clippy::needless_return,
+    clippy::needless_range_loop,
+    clippy::upper_case_acronyms
+)]
+
+mod gap_buffer;
+mod line_index;
+mod piece_table;
+mod rope;
+mod storage;
+
+use std::env;
+use std::time::Instant;
+
+use storage::{StorageKind, TextStorage};
+
+//Small xorshift generator, good enough to scatter edits around the text
+struct Random(u64);
+
+impl Random {
+    fn next(self: &mut Self, max: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % (max as u64 + 1)) as usize
+    }
+}
+
+//Usage: cargo run --release --bin bench [FILE] [EDITS]
+fn main () {
+    let mut args = env::args().skip(1);
+    let text = match args.next() {
+        Some(path) => std::fs::read_to_string(path).expect("cannot read file"),
+        None => "But I must explain to you how all this mistaken idea of denouncing pleasure.\n".repeat(20000)
+    };
+    let edits = args.next().and_then(|edits| edits.parse::<usize>().ok()).unwrap_or(20000);
+
+    println!("{} bytes, {} edits", text.len(), edits);
+    for kind in StorageKind::ALL {
+        let start = Instant::now();
+        let mut buffer = kind.create(&text);
+        let load = start.elapsed();
+
+        //Typing: runs of single chars at a few spots
+        let mut random = Random(0x2545f4914f6cdd1d);
+        let start = Instant::now();
+        let mut pos = 0;
+        for i in 0..edits {
+            if i % 50 == 0 {
+                pos = floor_boundary(buffer.as_ref(), random.next(buffer.len()));
+            }
+            buffer.insert_str(pos, "x");
+            pos += 1;
+        }
+        let typing = start.elapsed();
+
+        //Scattered edits: insert and delete anywhere
+        let start = Instant::now();
+        for _ in 0..edits {
+            let pos = floor_boundary(buffer.as_ref(), random.next(buffer.len()));
+            buffer.insert_str(pos, "hello");
+            let pos = floor_boundary(buffer.as_ref(), random.next(buffer.len()));
+            let end = floor_boundary(buffer.as_ref(), std::cmp::min(pos + 5, buffer.len()));
+            buffer.delete_range(pos..end);
+        }
+        let scattered = start.elapsed();
+
+        let start = Instant::now();
+        let chars = buffer.chars_at(0).count();
+        let lines = buffer.line_count();
+        let scan = start.elapsed();
+
+        println!(
+            "{:<12} load {:>10.2?}  typing {:>10.2?}  scattered {:>10.2?}  scan {:>10.2?}  ({} chars, {} lines)",
+            kind.name(), load, typing, scattered, scan, chars, lines
+        );
+    }
+}
+
+fn floor_boundary (buffer: &dyn TextStorage, pos: usize) -> usize {
+    if buffer.is_char_boundary(pos) {
+        pos
+    } else {
+        buffer.prev_char_boundary(pos)
+    }
+}
@@ -0,0 +1,109 @@
+use std::ops::Range;
+
+use crate::marks::{Gravity, MarkId, Marks};
+use crate::storage::{BufferError, CharsIter, TextStorage};
+
+//The text the editor works on: whichever storage was picked, plus the bookkeeping that has
+//to follow every edit. All changes go through here so nothing can miss one.
+pub struct Buffer {
+    storage: Box<dyn TextStorage>,
+    marks: Marks,
+}
+
+impl Buffer {
+    pub fn new(storage: Box<dyn TextStorage>) -> Self {
+        Buffer {
+            storage,
+            marks: Marks::new(),
+        }
+    }
+
+    pub fn add_mark(self: &mut Self, pos: usize, gravity: Gravity) -> MarkId {
+        self.marks.add(pos, gravity)
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_mark(self: &mut Self, id: MarkId) {
+        self.marks.remove(id);
+    }
+
+    pub fn mark(self: &Self, id: MarkId) -> Option<usize> {
+        self.marks.get(id)
+    }
+
+    pub fn set_mark(self: &mut Self, id: MarkId, pos: usize) {
+        self.marks.set(id, pos);
+    }
+}
+
+impl TextStorage for Buffer {
+    fn len(self: &Self) -> usize {
+        self.storage.len()
+    }
+
+    fn get(self: &Self, pos: usize) -> Result<char, BufferError> {
+        self.storage.get(pos)
+    }
+
+    fn is_char_boundary(self: &Self, pos: usize) -> bool {
+        self.storage.is_char_boundary(pos)
+    }
+
+    fn try_insert_str(self: &mut Self, pos: usize, text: &str) -> Result<(), BufferError> {
+        self.storage.try_insert_str(pos, text)?;
+        self.marks.insert(pos, text.len());
+        Ok(())
+    }
+
+    fn try_delete_range(self: &mut Self, range: Range<usize>) -> Result<(), BufferError> {
+        self.storage.try_delete_range(range.clone())?;
+        self.marks.delete(range);
+        Ok(())
+    }
+
+    fn chars_in(self: &Self, range: Range<usize>) -> CharsIter<'_> {
+        self.storage.chars_in(range)
+    }
+
+    //The storages may know faster ways than the defaults, pass everything else on too
+
+    fn chars_at(self: &Self, pos: usize) -> CharsIter<'_> {
+        self.storage.chars_at(pos)
+    }
+
+    fn text_range(self: &Self, range: Range<usize>) -> String {
+        self.storage.text_range(range)
+    }
+
+    fn text(self: &Self) -> String {
+        self.storage.text()
+    }
+
+    fn next_char_boundary(self: &Self, pos: usize) -> usize {
+        self.storage.next_char_boundary(pos)
+    }
+
+    fn prev_char_boundary(self: &Self, pos: usize) -> usize {
+        self.storage.prev_char_boundary(pos)
+    }
+
+    fn byte_to_char(self: &Self, pos: usize) -> usize {
+        self.storage.byte_to_char(pos)
+    }
+
+    fn char_to_byte(self: &Self, char_index: usize) -> usize {
+        self.storage.char_to_byte(char_index)
+    }
+
+    fn line_count(self: &Self) -> usize {
+        self.storage.line_count()
+    }
+
+    fn line_to_byte(self: &Self, line: usize) -> usize {
+        self.storage.line_to_byte(line)
+    }
+
+    fn byte_to_line(self: &Self, pos: usize) -> usize {
+        self.storage.byte_to_line(pos)
+    }
+}
@@ -1,16 +1,49 @@
 use std::{
     cmp,
-    io::{stdout, Write},
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
 
-use crossterm::{
-    cursor::MoveTo,
-    execute, queue,
-    style::{Print, SetBackgroundColor, SetForegroundColor},
+use crossterm::style::Color;
+
+use crate::backend::Backend;
+use crate::buffer::Buffer;
+use crate::file;
+use crate::history::{Edit, EditKind, History, Travel};
+use crate::line_map::LineMap;
+use crate::marks::{Gravity, MarkId};
+use crate::registers::Registers;
+use crate::screen::{self, Screen};
+use crate::storage::{StorageKind, TextStorage};
+use crate::swap::{self, SwapInfo};
+use crate::words::{self, WordChars};
+use crate::undo_file;
+
+const SWAP_INTERVAL: Duration = Duration::from_secs(2);
+//Clicks on the same cell closer together than this select words and then lines
+const MULTI_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+const TAB_WIDTH: u16 = 4;
+
+const BACKGROUND: Color = Color::Rgb {
+    r: 17,
+    g: 18,
+    b: 29,
+};
+const ACCENT: Color = Color::Rgb {
+    r: 238,
+    g: 109,
+    b: 133,
+};
+const TEXT: Color = Color::White;
+pub const SELECTION: Color = Color::Rgb {
+    r: 62,
+    g: 65,
+    b: 98,
 };
-
-use crate::gap_buffer::TextGapBuffer;
-use crate::logger;
 
 pub enum Direction {
     LEFT,
@@ -19,21 +52,47 @@ pub enum Direction {
     RIGHT,
 }
 
+//Text selected between `anchor`, where it was started, and `head`, which follows the cursor
+#[derive(Clone, Copy)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+}
+
+impl Selection {
+    pub fn range(self: &Self) -> Range<usize> {
+        cmp::min(self.anchor, self.head)..cmp::max(self.anchor, self.head)
+    }
+}
+
+//How much text a selection covers around its ends
+#[derive(Clone, Copy, PartialEq)]
+pub enum SelectionKind {
+    //Up to the cursor, which sits in between two chars
+    Exclusive,
+    //Including the char under the cursor, as in the visual mode of vim
+    Inclusive,
+    //Every line it touches, completely
+    Lines,
+}
+
 pub struct Cursor {
     row: u16,
     column: u16,
 }
 
+//A visual line, `index` and `len` are in bytes and `width` is in screen columns
+#[derive(Clone, Copy)]
 pub struct LineInfo {
     pub index: usize,
-    pub tabs: Vec<u16>,
     pub len: u16,
+    pub width: u16,
 }
 
 pub struct LineChunk {
     len: u16,
     end_of_line: bool,
-    tabs: Vec<u16>,
+    width: u16,
 }
 
 pub struct ScreenDimensions {
@@ -47,24 +106,96 @@ pub struct Editor {
     cursor: Cursor,
     window_dim: ScreenDimensions,
     editor_dim: ScreenDimensions,
-    gap_buffer: TextGapBuffer,
+    buffer: Buffer,
+    storage: StorageKind,
+    history: History,
+    bookmarks: HashMap<String, MarkId>,
+    word_chars: WordChars,
+    //Where the selection was started, its head is wherever the cursor is
+    anchor: Option<usize>,
+    selection_kind: SelectionKind,
+    registers: Registers,
+    //Copies also go to the system clipboard, through the terminal
+    osc52: bool,
+    //Time, cell and count of the last click, for telling double and triple clicks apart
+    last_click: Option<(Instant, u16, u16, u8)>,
 
     title: String,
+    status: String,
+    file_path: Option<PathBuf>,
+    modified: bool,
+    //History state the file on disk matches, `None` when no state does
+    saved_state: Option<usize>,
+    backup: bool,
+    swap_path: Option<PathBuf>,
+    swap_dirty: bool,
+    swap_written: Instant,
     line_offset: usize,
-    line_map: Vec<LineInfo>,
+    line_map: LineMap,
+    screen: Screen,
+    backend: Box<dyn Backend>,
 }
 
 impl Editor {
-    pub fn new(window_dim: ScreenDimensions, title: String) -> Self {
-        let mut gap_buffer = TextGapBuffer::new();
-        //let basic_string = "abc\tcde\tfgh\nxxxxxxxxxxxxxxxxx";
-        let basic_string = "But I must explain to you how all this mistaken idea of denouncing pleasure and praising pain was born and I will give you a complete account of the system, and expound the actual teachings of the great explorer of the truth, the master-builder of human happiness. No one rejects, dislikes, or avoids pleasure itself, because it is pleasure, but because those who do not know how to pursue pleasure rationally encounter consequences that are extremely painful. Nor again is there anyone who loves or pursues or desires to obtain pain of itself, because it is pain, but because occasionally circumstances occur in which toil and pain can procure him some great pleasure. To take a trivial example, which of us ever undertakes laborious physical exercise, except to obtain some advantage from it?\nBut who has any right to find fault with a man who chooses to enjoy a pleasure that has no annoying consequences, or one who avoids a pain that produces no resultant pleasure?";
-        let cols_limit = window_dim.max_cols - 4;
+    pub fn new(
+        window_dim: ScreenDimensions,
+        title: String,
+        storage: StorageKind,
+        backend: Box<dyn Backend>,
+    ) -> Self {
+        Editor::with_buffer(window_dim, title, storage, backend, "", None)
+    }
+
+    //Opens the file at `path`, a missing file gives an empty buffer that is created on save
+    pub fn open(
+        window_dim: ScreenDimensions,
+        title: String,
+        storage: StorageKind,
+        backend: Box<dyn Backend>,
+        path: &Path,
+    ) -> std::io::Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        log::info!("Opened {} ({} bytes)", path.display(), contents.len());
 
-        for i in 0..basic_string.len() {
-            gap_buffer.insert_ch(basic_string.chars().nth(i).unwrap())
+        let mut instance = Editor::with_buffer(
+            window_dim,
+            title,
+            storage,
+            backend,
+            &contents,
+            Some(path.to_path_buf()),
+        );
+
+        //Pick up the edit history of the previous session if the file is still as we left it
+        let history = undo_file::undo_path(path)
+            .and_then(|undo_path| undo_file::read(&undo_path, undo_file::content_hash(&contents)));
+        match history {
+            Ok(Some(history)) => {
+                instance.saved_state = Some(history.current_state());
+                instance.history = history;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                log::warn!("Undo history of {} not loaded: {}", path.display(), err);
+                instance.set_status(format!("Undo history not loaded: {}", err));
+            }
         }
 
+        Ok(instance)
+    }
+
+    fn with_buffer(
+        window_dim: ScreenDimensions,
+        title: String,
+        storage: StorageKind,
+        backend: Box<dyn Backend>,
+        contents: &str,
+        file_path: Option<PathBuf>,
+    ) -> Self {
         let editor_dim = ScreenDimensions {
             row: window_dim.row + 2,
             column: window_dim.column + 2,
@@ -72,191 +203,411 @@ impl Editor {
             max_cols: window_dim.max_cols - 4,
         };
 
+        let swap_path = match &file_path {
+            Some(path) => swap::swap_path(path).ok(),
+            None => None,
+        };
+        let screen = Screen::new(window_dim.max_cols, window_dim.max_rows);
+
         let mut instance = Self {
             cursor: Cursor { row: 0, column: 0 },
             window_dim,
             editor_dim,
-            gap_buffer,
+            buffer: Buffer::new(storage.create(contents)),
+            storage,
+            history: History::new(),
+            bookmarks: HashMap::new(),
+            word_chars: WordChars::default(),
+            anchor: None,
+            selection_kind: SelectionKind::Exclusive,
+            registers: Registers::new(),
+            osc52: false,
+            last_click: None,
 
             title,
+            status: String::new(),
+            file_path,
+            modified: false,
+            saved_state: Some(0),
+            backup: false,
+            swap_path,
+            swap_dirty: false,
+            swap_written: Instant::now(),
             line_offset: 0,
-            line_map: Vec::new(),
+            line_map: LineMap::new(Vec::new(), 0),
+            screen,
+            backend,
         };
 
-        instance.line_map = instance.get_line_map(cols_limit);
+        instance.line_map = instance.wrap_all();
         instance.draw_window();
         instance.draw_lines(0);
         instance.move_to_cursor();
         instance
     }
 
+    pub fn save(self: &mut Self) -> std::io::Result<()> {
+        let path = match &self.file_path {
+            Some(path) => path.clone(),
+            None => return Err(Error::new(ErrorKind::NotFound, "save: No file name")),
+        };
+
+        let contents = self.contents();
+        file::write_atomic(&path, contents.as_bytes(), self.backup)?;
+        self.modified = false;
+        //Typing on must not grow the saved state
+        self.history.break_step();
+        self.saved_state = Some(self.history.current_state());
+        self.discard_swap()?;
+        log::info!("Saved {} bytes to {}", contents.len(), path.display());
+
+        let undo_saved = undo_file::undo_path(&path).and_then(|undo_path| {
+            undo_file::write(&undo_path, undo_file::content_hash(&contents), &self.history)
+        });
+        match undo_saved {
+            Ok(()) => self.set_status(format!("Saved {} bytes", contents.len())),
+            Err(err) => {
+                log::warn!("Undo history of {} not stored: {}", path.display(), err);
+                self.set_status(format!(
+                    "Saved {} bytes, undo history not stored: {}",
+                    contents.len(),
+                    err
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn contents(self: &Self) -> String {
+        self.buffer.text()
+    }
+
+    //Replaces the whole buffer, putting the cursor back at the start. The undo history was
+    //recorded against the old text and goes with it.
+    fn load_str(self: &mut Self, contents: &str) {
+        self.buffer = Buffer::new(self.storage.create(contents));
+        self.history = History::new();
+        self.saved_state = None;
+        self.bookmarks.clear();
+        self.anchor = None;
+        self.cursor = Cursor { row: 0, column: 0 };
+        self.line_offset = 0;
+        self.line_map = self.wrap_all();
+
+        self.draw_window();
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
+    //Swap file left behind by an earlier session of this file, if any
+    pub fn find_swap(self: &Self) -> std::io::Result<Option<SwapInfo>> {
+        match &self.swap_path {
+            Some(swap_path) => swap::read(swap_path),
+            None => Ok(None),
+        }
+    }
+
+    pub fn recover_swap(self: &mut Self, swap_info: SwapInfo) {
+        self.load_str(&swap_info.contents);
+        self.swap_dirty = true;
+        self.modified = true;
+        self.set_status(String::from("Recovered unsaved changes from the swap file"));
+    }
+
+    pub fn discard_swap(self: &mut Self) -> std::io::Result<()> {
+        self.swap_dirty = false;
+        match &self.swap_path {
+            Some(swap_path) => swap::remove(swap_path),
+            None => Ok(()),
+        }
+    }
+
+    //Stops journaling, used when another live session owns the swap file
+    pub fn disable_swap(self: &mut Self) {
+        self.swap_path = None;
+        self.swap_dirty = false;
+    }
+
+    //Journals unsaved edits to the swap file, at most once every `SWAP_INTERVAL`
+    pub fn sync_swap(self: &mut Self) -> std::io::Result<()> {
+        if !self.swap_dirty || self.swap_written.elapsed() < SWAP_INTERVAL {
+            return Ok(());
+        }
+
+        self.swap_written = Instant::now();
+        if let (Some(swap_path), Some(file_path)) = (&self.swap_path, &self.file_path) {
+            swap::write(swap_path, file_path, &self.contents())?;
+        }
+
+        self.swap_dirty = false;
+        Ok(())
+    }
+
+    //Keeps the previous version of the file around as `file~` on every save
+    pub fn set_backup(self: &mut Self, backup: bool) {
+        self.backup = backup;
+    }
+
+    pub fn set_osc52(self: &mut Self, osc52: bool) {
+        self.osc52 = osc52;
+    }
+
+    pub fn word_chars(self: &Self) -> &WordChars {
+        &self.word_chars
+    }
+
+    //Chars besides letters and digits that count as part of a word
+    pub fn set_word_chars(self: &mut Self, extra: &str) {
+        self.word_chars = WordChars::new(extra);
+    }
+
+    pub fn set_status(self: &mut Self, status: String) {
+        self.status = status;
+        self.draw_bars();
+        self.move_to_cursor();
+    }
+
+    //Whether there are edits that have not been saved
+    pub fn is_modified(self: &Self) -> bool {
+        self.modified
+    }
+
+    fn set_modified(self: &mut Self) {
+        self.swap_dirty = true;
+        if !self.modified {
+            self.modified = true;
+            self.draw_bars();
+        }
+    }
+
     pub fn get_line_chunk(self: &Self, start: usize, limit: u16) -> LineChunk {
-        let mut last_space = 0;
+        let mut last_space = None;
+        let mut last_ch = None;
 
         let mut i = start;
-        let mut tabs = Vec::new();
-        let mut limit = limit as usize;
-        while limit > 0 {
-            let ch = match self.gap_buffer.get(i) {
-                Ok(ch) => ch,
-                Err(_) => {
-                    return LineChunk {
-                        len: (i - start) as u16,
-                        end_of_line: false,
-                        tabs,
-                    }
-                }
-            };
+        let mut width = 0;
+        for ch in self.buffer.chars_at(start) {
+            if ch == '\n' {
+                return LineChunk {
+                    len: (i - start) as u16,
+                    end_of_line: true,
+                    width,
+                };
+            }
 
-            match ch {
-                '\n' => {
-                    return LineChunk {
-                        len: (i - start) as u16,
-                        end_of_line: true,
-                        tabs,
-                    }
-                }
-                '\t' => {
-                    tabs.push((i - start) as u16);
-                    limit -= 4;
-                    i += 1;
-                    continue;
-                }
-                ' ' => last_space = i,
-                _ => {}
+            //A char wider than the whole line still gets a line of its own
+            let ch_width = char_width(ch);
+            if width + ch_width > limit && i > start {
+                break;
             }
 
-            i += 1;
-            limit -= 1;
+            i += ch.len_utf8();
+            width += ch_width;
+            last_ch = Some(ch);
+            if ch == ' ' {
+                last_space = Some((i, width));
+            }
         }
 
-        let ch = self.gap_buffer.get(i - 1).unwrap();
-        if i < self.gap_buffer.len() - 1 && ch != ' ' {
-            return LineChunk {
-                len: (last_space - start + 1) as u16,
-                end_of_line: false,
-                tabs,
-            };
+        if let Some((space_end, space_width)) = last_space {
+            if i < self.buffer.len() && last_ch != Some(' ') {
+                return LineChunk {
+                    len: (space_end - start) as u16,
+                    end_of_line: false,
+                    width: space_width,
+                };
+            }
         }
 
         return LineChunk {
             len: (i - start) as u16,
             end_of_line: false,
-            tabs,
+            width,
         };
     }
 
     pub fn get_line_map(self: &Self, limit: u16) -> Vec<LineInfo> {
-        let mut index = 0;
         let mut vec: Vec<LineInfo> = Vec::new();
+        let mut start = Some(0);
+        while let Some(index) = start {
+            start = self.wrap_line(index, limit, &mut vec);
+        }
+
+        return vec;
+    }
 
-        while index < self.gap_buffer.len() {
+    //Lays out the whole text again, needed whenever the width changes
+    fn wrap_all(self: &Self) -> LineMap {
+        LineMap::new(
+            self.get_line_map(self.editor_dim.max_cols),
+            self.buffer.len(),
+        )
+    }
+
+    //Pushes the visual lines of the logical line at `start`, returning where the next one starts
+    fn wrap_line(self: &Self, start: usize, limit: u16, vec: &mut Vec<LineInfo>) -> Option<usize> {
+        let mut index = start;
+        loop {
             let line_chunk = self.get_line_chunk(index, limit);
             vec.push(LineInfo {
                 index,
                 len: line_chunk.len,
-                tabs: line_chunk.tabs.clone(),
+                width: line_chunk.width,
             });
-            index += line_chunk.len as usize + (if line_chunk.end_of_line { 1 } else { 0 });
-            if line_chunk.end_of_line && index >= self.gap_buffer.len() {
-                vec.push(LineInfo {
-                    index,
-                    len: 0,           //line_chunk.len,
-                    tabs: Vec::new(), //line_chunk.tabs.clone()
-                });
+            index += line_chunk.len as usize;
+
+            if line_chunk.end_of_line {
+                return Some(index + 1);
+            }
+            if index >= self.buffer.len() {
+                return None;
+            }
+        }
+    }
+
+    //Re-wraps only the logical lines touched by replacing `old_len` bytes at `start` with
+    //`new_len` bytes, the buffer already holds the new text
+    fn update_line_map(self: &mut Self, start: usize, old_len: usize, new_len: usize) {
+        let line_start = start
+            - self
+                .buffer
+                .chars_rev_at(start)
+                .take_while(|ch| *ch != '\n')
+                .map(|ch| ch.len_utf8())
+                .sum::<usize>();
+
+        let mut next_start = None;
+        let mut index = start + new_len;
+        for ch in self.buffer.chars_at(index) {
+            index += ch.len_utf8();
+            if ch == '\n' {
+                next_start = Some(index);
+                break;
             }
         }
 
-        return vec;
+        let mut rows = Vec::new();
+        let mut line = Some(line_start);
+        while let Some(index) = line {
+            if line == next_start {
+                break;
+            }
+            line = self.wrap_line(index, self.editor_dim.max_cols, &mut rows);
+        }
+
+        //The map still describes the old text, where the next line started `old_len - new_len`
+        //bytes further on
+        let first_row = self.line_map.find(line_start);
+        let end_row = match next_start {
+            Some(next_start) => self.line_map.find(next_start - new_len + old_len),
+            None => self.line_map.len(),
+        };
+        self.line_map.replace(first_row..end_row, rows, self.buffer.len());
     }
 
+    //Snaps a column inside a tab onto its start or end, whichever is closer
     fn get_tab_rectified(self: &Self, line: &LineInfo, column: u16) -> u16 {
-        let tabs = &line.tabs;
-        for i in 0..tabs.len() {
-            let tab = tabs[i] + (i as u16 * 3);
-            if column >= tab && column < tab + (4 / 2) {
-                return tab;
-            } else if column >= tab + (4 / 2) && column < tab + 4 {
-                return tab + 4;
+        let mut col = 0;
+        for ch in self.line_chars(line) {
+            let ch_width = char_width(ch);
+            if column < col + ch_width {
+                return if column >= col + (ch_width / 2) && ch_width > 1 {
+                    col + ch_width
+                } else {
+                    col
+                };
             }
+
+            col += ch_width;
         }
 
         column
     }
 
     fn get_rel_cursor(self: &Self) -> Cursor {
-        let line = &self.line_map[self.line_offset + (self.cursor.row as usize)];
-        let column = std::cmp::min(
-            self.cursor.column,
-            line.len + (line.tabs.len() as u16 * 3), //tab_size - 1
-        );
+        let line = self
+            .line_map
+            .get(self.line_offset + (self.cursor.row as usize));
+        let column = std::cmp::min(self.cursor.column, line.width);
 
-        logger::log(format!("get_rel_cursor: {}", column).as_str());
+        log::trace!("get_rel_cursor: {}", column);
         Cursor {
             row: self.cursor.row,
-            column: self.get_tab_rectified(line, column),
+            column: self.get_tab_rectified(&line, column),
         }
     }
 
+    //The text, for looking at it around the cursor
+    pub fn storage(self: &Self) -> &dyn TextStorage {
+        &self.buffer
+    }
+
+    pub fn registers(self: &mut Self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn cursor_index(self: &Self) -> usize {
+        self.get_current_index()
+    }
+
+    //Puts the cursor at a buffer index, scrolling to it
+    pub fn move_to_index(self: &mut Self, index: usize) {
+        self.history.seal();
+        self.set_cursor_index(index);
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
     fn get_current_index(self: &Self) -> usize {
         let rel_cursor = self.get_rel_cursor();
 
         let line_index = self.line_offset + rel_cursor.row as usize;
-        let line = &self.line_map[line_index];
+        let line = self.line_map.get(line_index);
 
-        let tabs = &line.tabs;
-        for i in 0..tabs.len() {
-            let col = rel_cursor.column - (i as u16 * 3);
-            if col < tabs[i] {
-                return line.index + col as usize;
-            } else if col >= tabs[i] && col < tabs[i] + (4 / 2) {
-                return line.index + tabs[i] as usize;
-            } else if col >= tabs[i] + (4 / 2) && col < tabs[i] + 4 {
-                return line.index + tabs[i] as usize + 1;
+        let mut col = 0;
+        let mut i = line.index;
+        for ch in self.line_chars(&line) {
+            if rel_cursor.column <= col {
+                return i;
             }
+
+            col += char_width(ch);
+            i += ch.len_utf8();
         }
 
-        line.index + rel_cursor.column as usize - ((4 - 1) * tabs.len())
+        line.index + line.len as usize
     }
 
-    fn get_index_line(self: &Self, index: usize) -> usize {
-        for i in 0..(self.line_map.len() - 1) {
-            let line = &self.line_map[i];
-            let next_line = &self.line_map[i + 1];
-            if index >= line.index && index < next_line.index {
-                return i;
-            }
-        }
+    fn line_chars<'a>(self: &'a Self, line: &LineInfo) -> impl Iterator<Item = char> + 'a {
+        self.buffer.chars_in(line.index..line.index + line.len as usize)
+    }
 
-        return self.line_map.len() - 1;
+    //Visual line holding `index`, the last one that starts at or before it
+    fn get_index_line(self: &Self, index: usize) -> usize {
+        self.line_map.find(index)
     }
 
     fn get_cursor_from_index(self: &Self, index: usize) -> Cursor {
         let start_line = self.line_offset;
         let line_index = self.get_index_line(index);
-        let line = &self.line_map[line_index];
+        let line = self.line_map.get(line_index);
 
-        let mut found_tabs = 0;
-        for i in 0..line.tabs.len() {
-            if line.index + line.tabs[i] as usize >= index {
+        let mut column = 0;
+        let mut i = line.index;
+        for ch in self.line_chars(&line) {
+            if i >= index {
                 break;
             }
 
-            found_tabs += 1;
+            column += char_width(ch);
+            i += ch.len_utf8();
         }
 
-        logger::log(
-            format!(
-                "get_cursor_from_index: {} -> {}",
-                index,
-                (index + (found_tabs * 3)) - line.index
-            )
-            .as_str(),
-        );
+        log::trace!("get_cursor_from_index: {} -> {}", index, column);
 
         return Cursor {
             row: (line_index - start_line) as u16,
-            column: ((index + (found_tabs * 3)) - line.index) as u16,
+            column,
         };
     }
 
@@ -270,33 +621,34 @@ impl Editor {
             self.cursor.row = self.editor_dim.max_rows - 2;
         }
 
-        logger::log(
-            format!(
-                "Cursor: [row: {}, column: {}]",
-                rel_cursor.row, rel_cursor.column
-            )
-            .as_str(),
+        //The highlight follows the head of the selection
+        if self.anchor.is_some() {
+            self.draw_lines(0);
+        }
+
+        log::trace!(
+            "Cursor: [row: {}, column: {}]",
+            rel_cursor.row,
+            rel_cursor.column
         );
-        execute!(
-            stdout(),
-            MoveTo(
-                rel_cursor.column + self.editor_dim.column,
-                rel_cursor.row + self.editor_dim.row
-            )
-        )
-        .unwrap();
+        self.screen.set_cursor(
+            rel_cursor.column + self.editor_dim.column,
+            rel_cursor.row + self.editor_dim.row,
+        );
+        self.screen.flush(self.backend.as_mut()).unwrap();
     }
 
     //TODO: Handle line offset, here itself
-    pub fn move_cursor(self: &mut Self, direction: Direction, magnitude: u16) {
+    pub fn move_cursor(self: &mut Self, direction: Direction, _magnitude: u16) {
         let mut redraw_lines = false;
+        self.history.seal();
 
         match direction {
             Direction::LEFT => {
                 let index = self.get_current_index();
-                logger::log(format!("Index: {}", index).as_str());
-                if index as isize - 1 >= 0 {
-                    let cur = self.get_cursor_from_index(index - 1);
+                log::trace!("Index: {}", index);
+                if index > 0 {
+                    let cur = self.get_cursor_from_index(self.buffer.prev_char_boundary(index));
                     self.cursor.row = cur.row;
                     self.cursor.column = cur.column;
                 }
@@ -307,28 +659,25 @@ impl Editor {
                     self.line_map.len() - self.line_offset,
                 );
 
-                logger::log(
-                    format!(
-                        "[BEFORE] cursor.row = {}, rows = {}, line_map = {}",
-                        self.cursor.row,
-                        self.editor_dim.max_rows,
-                        self.line_map.len()
-                    )
-                    .as_str(),
+                log::trace!(
+                    "[BEFORE] cursor.row = {}, rows = {}, line_map = {}",
+                    self.cursor.row,
+                    self.editor_dim.max_rows,
+                    self.line_map.len()
                 );
                 self.cursor.row = if self.cursor.row + 1 == (line_offset_max_limit as u16) {
                     if (self.line_offset + self.editor_dim.max_rows as usize) < self.line_map.len()
                     {
                         redraw_lines = true;
                         self.line_offset += 1;
-                        logger::log(format!("Line Offset incremented").as_str());
+                        log::trace!("Line Offset incremented");
                     }
                     line_offset_max_limit as u16 - 1
                 } else {
-                    logger::log(format!("cursor incremented").as_str());
+                    log::trace!("cursor incremented");
                     self.cursor.row + 1
                 };
-                logger::log(format!("[AFTER] cursor.row = {}", self.cursor.row).as_str());
+                log::trace!("[AFTER] cursor.row = {}", self.cursor.row);
             }
             Direction::UP => {
                 self.cursor.row = if self.cursor.row == 0 {
@@ -343,9 +692,9 @@ impl Editor {
             }
             Direction::RIGHT => {
                 let index = self.get_current_index();
-                logger::log(format!("Index: {}", index).as_str());
-                if index < self.gap_buffer.len() {
-                    let cur = self.get_cursor_from_index(index + 1);
+                log::trace!("Index: {}", index);
+                if index < self.buffer.len() {
+                    let cur = self.get_cursor_from_index(self.buffer.next_char_boundary(index));
                     self.cursor.row = cur.row;
                     self.cursor.column = cur.column;
                 }
@@ -359,106 +708,370 @@ impl Editor {
         self.move_to_cursor();
     }
 
-    fn draw_window(self: &Self) {
-        queue!(
-            stdout(),
-            SetBackgroundColor(crossterm::style::Color::Rgb {
-                r: 17,
-                g: 18,
-                b: 29
-            }),
-            SetForegroundColor(crossterm::style::Color::Rgb {
-                r: 238,
-                g: 109,
-                b: 133
-            }),
-        )
-        .unwrap();
+    //With Shift held down the first move drops the anchor where the cursor is and later ones
+    //move the head, any other move ends the selection
+    pub fn set_selecting(self: &mut Self, selecting: bool) {
+        if selecting {
+            if self.anchor.is_none() {
+                self.start_selection(self.get_current_index());
+            }
+        } else if self.anchor.take().is_some() {
+            self.draw_lines(0);
+            self.move_to_cursor();
+        }
+    }
+
+    fn start_selection(self: &mut Self, anchor: usize) {
+        self.anchor = Some(anchor);
+        self.selection_kind = SelectionKind::Exclusive;
+    }
+
+    //Selects from `anchor` to the cursor, or drops the selection
+    pub fn set_selection(self: &mut Self, anchor: Option<usize>, kind: SelectionKind) {
+        self.anchor = anchor;
+        self.selection_kind = kind;
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
+    pub fn selection(self: &Self) -> Option<Selection> {
+        self.anchor.map(|anchor| Selection {
+            anchor,
+            head: self.get_current_index(),
+        })
+    }
+
+    //Selected bytes, `None` when nothing or an empty range is selected
+    fn selected_range(self: &Self) -> Option<Range<usize>> {
+        let range = self.selection()?.range();
+        let range = match self.selection_kind {
+            SelectionKind::Exclusive => range,
+            SelectionKind::Inclusive => range.start..self.buffer.next_char_boundary(range.end),
+            SelectionKind::Lines => {
+                let end = self.logical_line_end(range.end);
+                self.logical_line_start(range.start)..cmp::min(end + 1, self.buffer.len())
+            }
+        };
 
+        Some(range).filter(|range| !range.is_empty())
+    }
+
+    //Deletes the selected text, false when there was none
+    fn delete_selection(self: &mut Self) -> bool {
+        match self.selected_range() {
+            Some(range) => {
+                let head = self.get_current_index();
+                self.anchor = None;
+                self.delete_range(range, head);
+                true
+            }
+            None => {
+                self.anchor = None;
+                false
+            }
+        }
+    }
+
+    //Places the cursor at a cell of the window. A second click on the same cell selects the
+    //word there and a third one the whole line, with Shift the selection is extended instead.
+    pub fn click(self: &mut Self, column: u16, row: u16, extend: bool) {
+        self.history.seal();
+        let count = match self.last_click {
+            Some((time, last_column, last_row, count))
+                if (last_column, last_row) == (column, row)
+                    && time.elapsed() < MULTI_CLICK_INTERVAL =>
+            {
+                count % 3 + 1
+            }
+            _ => 1,
+        };
+        self.last_click = Some((Instant::now(), column, row, count));
+
+        let index = self.index_at(column, row);
+        let range = match count {
+            2 => words::word_at(&self.buffer, index, &self.word_chars),
+            3 => {
+                let end = self.logical_line_end(index);
+                let end = cmp::min(end + 1, self.buffer.len());
+                self.logical_line_start(index)..end
+            }
+            _ => index..index,
+        };
+
+        if extend && count == 1 {
+            if self.anchor.is_none() {
+                self.start_selection(self.get_current_index());
+            }
+        } else if count == 1 {
+            self.anchor = None;
+        } else {
+            self.start_selection(range.start);
+        }
+        self.set_cursor_index(range.end);
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
+    //Moving the mouse with the button held down selects from where it was pressed
+    pub fn drag_to(self: &mut Self, column: u16, row: u16) {
+        if self.anchor.is_none() {
+            self.start_selection(self.get_current_index());
+        }
+
+        //Dragging past the top or bottom edge scrolls
+        let max_rows = self.editor_dim.max_rows;
+        if row < self.editor_dim.row {
+            self.line_offset = self.line_offset.saturating_sub(1);
+        } else if row >= self.editor_dim.row + max_rows
+            && self.line_offset + (max_rows as usize) < self.line_map.len()
+        {
+            self.line_offset += 1;
+        }
+
+        let index = self.index_at(column, row);
+        self.set_cursor_index(index);
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
+    //Scrolls by `rows`, up when negative. The cursor stays on its text while that is on
+    //screen and is pushed along at the edges otherwise.
+    pub fn scroll(self: &mut Self, rows: isize) {
+        let max_rows = self.editor_dim.max_rows as usize;
+        let max_offset = self.line_map.len().saturating_sub(max_rows);
+        let line_offset = self.line_offset.saturating_add_signed(rows).min(max_offset);
+        if line_offset == self.line_offset {
+            return;
+        }
+
+        let line_index = self.line_offset + self.cursor.row as usize;
+        self.line_offset = line_offset;
+        self.cursor.row = line_index.clamp(line_offset, line_offset + max_rows - 1) as u16
+            - line_offset as u16;
+
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
+    //Buffer index shown at a cell of the window, cells outside the text snap to the closest one
+    fn index_at(self: &Self, column: u16, row: u16) -> usize {
+        let max_row = cmp::min(
+            self.editor_dim.max_rows as usize,
+            self.line_map.len() - self.line_offset,
+        ) - 1;
+        let row = cmp::min(row.saturating_sub(self.editor_dim.row) as usize, max_row);
+        let line = self.line_map.get(self.line_offset + row);
+        let column = column.saturating_sub(self.editor_dim.column);
+        let column = self.get_tab_rectified(&line, cmp::min(column, line.width));
+
+        let mut col = 0;
+        let mut index = line.index;
+        for ch in self.line_chars(&line) {
+            if column <= col {
+                break;
+            }
+            col += char_width(ch);
+            index += ch.len_utf8();
+        }
+
+        index
+    }
+
+    //Start of the word before the cursor
+    pub fn move_word_left(self: &mut Self) {
+        self.history.seal();
+        let index = self.get_current_index();
+        self.set_cursor_index(words::word_start(&self.buffer, index, &self.word_chars));
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
+    //End of the word after the cursor
+    pub fn move_word_right(self: &mut Self) {
+        self.history.seal();
+        let index = self.get_current_index();
+        self.set_cursor_index(words::word_end(&self.buffer, index, &self.word_chars));
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
+    //Start of the visual line, or of the logical line when the cursor is already there
+    pub fn move_home(self: &mut Self) {
+        self.history.seal();
+        if self.get_rel_cursor().column > 0 {
+            self.cursor.column = 0;
+        } else {
+            let index = self.get_current_index();
+            self.set_cursor_index(self.logical_line_start(index));
+            self.draw_lines(0);
+        }
+
+        self.move_to_cursor();
+    }
+
+    //End of the visual line, or of the logical line when the cursor is already there
+    pub fn move_end(self: &mut Self) {
+        self.history.seal();
+        let line = self
+            .line_map
+            .get(self.line_offset + self.cursor.row as usize);
+        if self.get_rel_cursor().column < line.width {
+            self.cursor.column = line.width;
+        } else {
+            let index = self.get_current_index();
+            self.set_cursor_index(self.logical_line_end(index));
+            self.draw_lines(0);
+        }
+
+        self.move_to_cursor();
+    }
+
+    //Scrolls a screenful down keeping the cursor on the same row, the last page moves the
+    //cursor to the last line instead
+    pub fn page_down(self: &mut Self) {
+        self.history.seal();
+        let max_rows = self.editor_dim.max_rows as usize;
+        let max_offset = self.line_map.len().saturating_sub(max_rows);
+        if self.line_offset < max_offset {
+            self.line_offset = cmp::min(self.line_offset + max_rows, max_offset);
+            self.draw_lines(0);
+        } else {
+            self.cursor.row = (self.line_map.len() - 1 - self.line_offset) as u16;
+        }
+
+        self.move_to_cursor();
+    }
+
+    //Scrolls a screenful up keeping the cursor on the same row, the first page moves the
+    //cursor to the first line instead
+    pub fn page_up(self: &mut Self) {
+        self.history.seal();
+        if self.line_offset > 0 {
+            self.line_offset = self
+                .line_offset
+                .saturating_sub(self.editor_dim.max_rows as usize);
+            self.draw_lines(0);
+        } else {
+            self.cursor.row = 0;
+        }
+
+        self.move_to_cursor();
+    }
+
+    pub fn move_to_start(self: &mut Self) {
+        self.history.seal();
+        self.set_cursor_index(0);
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
+    pub fn move_to_end(self: &mut Self) {
+        self.history.seal();
+        self.set_cursor_index(self.buffer.len());
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
+    fn logical_line_start(self: &Self, index: usize) -> usize {
+        self.buffer.line_to_byte(self.buffer.byte_to_line(index))
+    }
+
+    //Where the logical line holding `index` ends, right before its newline
+    fn logical_line_end(self: &Self, index: usize) -> usize {
+        let line = self.buffer.byte_to_line(index);
+        if line + 1 < self.buffer.line_count() {
+            self.buffer.line_to_byte(line + 1) - 1
+        } else {
+            self.buffer.len()
+        }
+    }
+
+    //Only fills the frame in memory, it reaches the terminal with the next `move_to_cursor`
+    fn draw_window(self: &mut Self) {
         for i in 0..self.window_dim.max_rows {
-            queue!(
-                stdout(),
-                MoveTo(self.window_dim.column, self.window_dim.row + i),
-                Print(
-                    (0..self.window_dim.max_cols)
-                        .map(|_| " ")
-                        .collect::<String>()
-                        .as_str()
-                ),
-            )
-            .unwrap();
-        }
-
-        queue!(
-            stdout(),
-            MoveTo(self.window_dim.column, self.window_dim.row),
-            Print(
-                (0..self.window_dim.max_cols)
-                    .map(|_| " ")
-                    .collect::<String>()
-                    .as_str()
-            ),
-            MoveTo(self.editor_dim.column, self.window_dim.row),
-            Print(self.title.clone()),
-            MoveTo(
+            self.screen.fill(
                 self.window_dim.column,
-                self.window_dim.row + self.window_dim.max_rows
-            ),
-            Print(
-                (0..self.window_dim.max_cols)
-                    .map(|_| " ")
-                    .collect::<String>()
-                    .as_str()
-            ),
-            MoveTo(
-                self.editor_dim.column,
-                self.window_dim.row + self.window_dim.max_rows
-            ),
-            Print("==="),
-        )
-        .unwrap();
+                self.window_dim.row + i,
+                self.window_dim.max_cols,
+                ACCENT,
+                BACKGROUND,
+            );
+        }
 
-        stdout().flush().unwrap();
+        self.draw_bars();
     }
 
-    fn draw_lines(self: &Self, start_line: u16) {
-        queue!(
-            stdout(),
-            SetBackgroundColor(crossterm::style::Color::Rgb {
-                r: 17,
-                g: 18,
-                b: 29
-            }),
-            SetForegroundColor(crossterm::style::Color::White),
-        )
-        .unwrap();
+    fn draw_bars(self: &mut Self) {
+        let file_name = match &self.file_path {
+            Some(path) => path.display().to_string(),
+            None => String::from("[No Name]"),
+        };
+        let title = format!(
+            "{} {}{}",
+            self.title,
+            file_name,
+            if self.modified { " [+]" } else { "" }
+        );
+        let status_row = self.window_dim.row + self.window_dim.max_rows - 1;
 
-        for i in 0..self.editor_dim.max_rows {
-            queue!(
-                stdout(),
-                MoveTo(self.editor_dim.column, self.editor_dim.row + i),
-                Print(
-                    (0..self.editor_dim.max_cols)
-                        .map(|_| " ")
-                        .collect::<String>()
-                        .as_str()
-                ),
-            )
-            .unwrap();
+        for row in [self.window_dim.row, status_row] {
+            self.screen.fill(
+                self.window_dim.column,
+                row,
+                self.window_dim.max_cols,
+                ACCENT,
+                BACKGROUND,
+            );
         }
+        self.screen.print(
+            self.editor_dim.column,
+            self.window_dim.row,
+            &title,
+            ACCENT,
+            BACKGROUND,
+        );
+        self.screen.print(
+            self.editor_dim.column,
+            status_row,
+            &format!("=== {}", self.status),
+            ACCENT,
+            BACKGROUND,
+        );
+    }
 
+    fn draw_lines(self: &mut Self, _start_line: u16) {
+        let selected = self.selected_range();
+        let is_selected =
+            |index: usize| selected.as_ref().is_some_and(|range| range.contains(&index));
         for i in 0..self.editor_dim.max_rows {
+            self.screen.fill(
+                self.editor_dim.column,
+                self.editor_dim.row + i,
+                self.editor_dim.max_cols,
+                TEXT,
+                BACKGROUND,
+            );
             if self.line_offset + (i as usize) >= self.line_map.len() {
-                break;
+                continue;
             }
 
             let line_index = self.line_offset + i as usize;
-            let line = &self.line_map[line_index];
+            let line = self.line_map.get(line_index);
             let mut line_str = String::new();
-            for i in line.index..(line.index + line.len as usize) {
-                let ch = self.gap_buffer.get(i).unwrap();
+            let mut column = 0;
+            //Columns of the selected part of the line
+            let mut highlight = None;
+            let mut index = line.index;
+            for ch in self.line_chars(&line) {
+                if is_selected(index) {
+                    let start = highlight.map_or(column, |(start, _)| start);
+                    highlight = Some((start, column + char_width(ch) as usize));
+                }
+                index += ch.len_utf8();
+                column += char_width(ch) as usize;
+
                 if ch == '\t' {
-                    for _ in 0..4 {
+                    for _ in 0..TAB_WIDTH {
                         line_str.push(' ');
                     }
                     continue;
@@ -466,74 +1079,383 @@ impl Editor {
                 line_str.push(ch);
             }
 
-            queue!(
-                stdout(),
-                MoveTo(self.editor_dim.column, self.editor_dim.row + (i as u16)),
-                Print(
-                    (0..self.editor_dim.max_cols)
-                        .map(|_| " ")
-                        .collect::<String>()
-                        .as_str()
-                ),
-                MoveTo(self.editor_dim.column, self.editor_dim.row + (i as u16)),
-                Print(line_str)
-            )
-            .unwrap();
-        }
+            //A selected line break shows up as one more cell
+            if is_selected(index) && self.buffer.chars_at(index).next() == Some('\n') {
+                let start = highlight.map_or(column, |(start, _)| start);
+                highlight = Some((start, column + 1));
+            }
 
-        stdout().flush().unwrap();
+            self.screen.print(
+                self.editor_dim.column,
+                self.editor_dim.row + i,
+                &line_str,
+                TEXT,
+                BACKGROUND,
+            );
+            if let Some((start, end)) = highlight {
+                let mut column = 0;
+                let text: String = line_str
+                    .chars()
+                    .chain([' '])
+                    .filter(|ch| {
+                        let ch_column = column;
+                        column += screen::display_width(*ch) as usize;
+                        (start..end).contains(&ch_column)
+                    })
+                    .collect();
+                self.screen.print(
+                    self.editor_dim.column + start as u16,
+                    self.editor_dim.row + i,
+                    &text,
+                    TEXT,
+                    SELECTION,
+                );
+            }
+        }
     }
 
     pub fn insert_ch(self: &mut Self, ch: char) {
-        let curr_index = self.get_current_index();
-        self.gap_buffer.move_window(curr_index);
-        self.gap_buffer.insert_ch(ch);
+        self.insert_str(ch.encode_utf8(&mut [0; 4]));
+    }
 
-        self.line_map = self.get_line_map(self.editor_dim.max_cols);
+    //Inserts `text` at the cursor in one go, anything longer than a char is its own undo step
+    pub fn insert_str(self: &mut Self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
 
-        let new_cursor = self.get_cursor_from_index(curr_index + 1);
-        self.cursor.row = new_cursor.row;
-        self.cursor.column = new_cursor.column;
+        if let Some(range) = self.selected_range() {
+            return self.replace_range(range, text);
+        }
+        self.anchor = None;
 
-        let line_offset_max_limit =
-            cmp::min(self.editor_dim.max_rows as usize, self.line_map.len());
-        if self.cursor.row == (line_offset_max_limit as u16) {
-            if (self.line_offset + self.editor_dim.max_rows as usize) < self.line_map.len() {
-                self.cursor.row -= 1;
-                self.line_offset += 1;
-            }
+        let curr_index = self.get_current_index();
+        let single_ch = text.chars().nth(1).is_none();
+        if !single_ch {
+            self.history.seal();
         }
 
+        self.buffer.insert_str(curr_index, text);
+        self.history.record_insert(curr_index, text, curr_index);
+        if !single_ch {
+            self.history.seal();
+        }
+        self.set_modified();
+
+        self.update_line_map(curr_index, 0, text.len());
+        self.set_cursor_index(curr_index + text.len());
+
         self.draw_lines(0);
         self.move_to_cursor();
     }
 
     pub fn delete_ch(self: &mut Self) {
+        if self.delete_selection() {
+            return;
+        }
+
         let curr_index = self.get_current_index();
         if curr_index == 0 {
             return;
         }
 
-        self.gap_buffer.move_window(curr_index);
-        self.gap_buffer.delete_ch();
+        let prev_index = self.buffer.prev_char_boundary(curr_index);
+        self.delete_range(prev_index..curr_index, curr_index);
+    }
+
+    //Deletes the char under the cursor, joining the next line at the end of a line
+    pub fn delete_next_ch(self: &mut Self) {
+        if self.delete_selection() {
+            return;
+        }
+
+        let curr_index = self.get_current_index();
+        if curr_index >= self.buffer.len() {
+            return;
+        }
+
+        let next_index = self.buffer.next_char_boundary(curr_index);
+        self.delete_range(curr_index..next_index, curr_index);
+    }
+
+    //Deletes back to the start of the word before the cursor
+    pub fn delete_word_back(self: &mut Self) {
+        if self.delete_selection() {
+            return;
+        }
+
+        let curr_index = self.get_current_index();
+        let start = words::word_start(&self.buffer, curr_index, &self.word_chars);
+        self.delete_range(start..curr_index, curr_index);
+    }
+
+    //Deletes up to the end of the word after the cursor
+    pub fn delete_word_forward(self: &mut Self) {
+        if self.delete_selection() {
+            return;
+        }
+
+        let curr_index = self.get_current_index();
+        let end = words::word_end(&self.buffer, curr_index, &self.word_chars);
+        self.delete_range(curr_index..end, curr_index);
+    }
+
+    //Deletes the bytes in `range`, leaving the cursor where the text used to start
+    fn delete_range(self: &mut Self, range: Range<usize>, cursor_before: usize) {
+        if range.start >= range.end {
+            return;
+        }
+
+        let text = self.get_text(range.clone());
+        self.buffer.delete_range(range.clone());
+        self.history.record_delete(range.start, &text, cursor_before);
+        self.set_modified();
 
-        self.line_map = self.get_line_map(self.editor_dim.max_cols);
+        self.update_line_map(range.start, range.end - range.start, 0);
         if self.line_offset > 0
             && self.line_map.len() - self.line_offset < self.editor_dim.max_rows as usize
         {
             self.line_offset -= 1;
+        }
+        self.set_cursor_index(range.start);
+
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
+    //Copies the selection onto the kill ring, and into `register` when one is named
+    pub fn copy(self: &mut Self, register: Option<&str>) {
+        let range = match self.selected_range() {
+            Some(range) => range,
+            None => return self.set_status(String::from("Nothing selected")),
+        };
+
+        let text = self.get_text(range);
+        self.registers.store(register, &text);
+        if self.osc52 {
+            if let Err(err) = self.backend.set_clipboard(&text) {
+                log::warn!("Clipboard export failed: {}", err);
+            }
+        }
+
+        self.set_status(format!("Copied {} bytes", text.len()));
+    }
+
+    pub fn cut(self: &mut Self, register: Option<&str>) {
+        if self.selected_range().is_some() {
+            self.copy(register);
+            self.history.seal();
+            self.delete_selection();
+        } else {
+            self.set_status(String::from("Nothing selected"));
+        }
+    }
+
+    //Inserts the latest cut or copy, or the text of `register`, replacing the selection
+    pub fn paste(self: &mut Self, register: Option<&str>) {
+        let text = match self.registers.get(register) {
+            Some(text) => String::from(text),
+            None => {
+                return self.set_status(match register {
+                    Some(name) => format!("Register '{}' is empty", name),
+                    None => String::from("Nothing to paste"),
+                })
+            }
+        };
+
+        self.insert_pasted(&text);
+    }
+
+    //Text pasted into the terminal. It goes in as it is, in one go, instead of key by key through
+    //the key handling. Terminals send line breaks in pastes as `\r`.
+    pub fn paste_text(self: &mut Self, text: &str) {
+        self.insert_pasted(&text.replace("\r\n", "\n").replace('\r', "\n"));
+    }
+
+    //Inserts `text` in one go as an undo step of its own
+    fn insert_pasted(self: &mut Self, text: &str) {
+        self.history.seal();
+        self.insert_str(text);
+        self.history.seal();
+    }
+
+    //Edits until `end_undo_step` are undone together, for vim changes that go on in insert mode
+    pub fn begin_undo_step(self: &mut Self) {
+        self.history.begin_step();
+    }
+
+    pub fn end_undo_step(self: &mut Self) {
+        self.history.end_step();
+    }
+
+    //Puts `text` in place of the bytes in `range` as an undo step of its own
+    pub fn replace(self: &mut Self, range: Range<usize>, text: &str) {
+        self.history.seal();
+        self.replace_range(range, text);
+        self.history.seal();
+    }
+
+    //Puts `text` in place of the bytes in `range` as one undo step, the cursor ends up after it
+    fn replace_range(self: &mut Self, range: Range<usize>, text: &str) {
+        let cursor_before = self.get_current_index();
+        self.anchor = None;
+
+        let deleted = self.get_text(range.clone());
+        self.buffer.delete_range(range.clone());
+        self.buffer.insert_str(range.start, text);
+        self.history
+            .record_replace(range.start, &deleted, text, cursor_before);
+        self.set_modified();
+
+        self.update_line_map(range.start, range.end - range.start, text.len());
+        self.set_cursor_index(range.start + text.len());
+
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
+    fn get_text(self: &Self, range: Range<usize>) -> String {
+        self.buffer.text_range(range)
+    }
+
+    //Remembers the cursor position under `name`, it moves along with the text around it
+    pub fn set_bookmark(self: &mut Self, name: &str) {
+        let index = self.get_current_index();
+        match self.bookmarks.get(name) {
+            Some(&id) => self.buffer.set_mark(id, index),
+            None => {
+                let id = self.buffer.add_mark(index, Gravity::Left);
+                self.bookmarks.insert(String::from(name), id);
+            }
+        }
+
+        self.set_status(format!("Mark '{}' set", name));
+    }
+
+    pub fn jump_to_bookmark(self: &mut Self, name: &str) {
+        let index = match self.bookmarks.get(name).and_then(|&id| self.buffer.mark(id)) {
+            Some(index) => index,
+            None => return self.set_status(format!("No mark named '{}'", name)),
+        };
+
+        self.history.seal();
+        self.set_cursor_index(index);
+        self.draw_lines(0);
+        self.move_to_cursor();
+    }
+
+    pub fn undo(self: &mut Self) {
+        match self.history.undo() {
+            Some(path) => self.apply_edits(&path.edits, path.cursor),
+            None => self.set_status(String::from("Already at oldest change")),
+        }
+    }
+
+    pub fn redo(self: &mut Self) {
+        match self.history.redo() {
+            Some(path) => self.apply_edits(&path.edits, path.cursor),
+            None => self.set_status(String::from("Already at newest change")),
+        }
+    }
+
+    //Moves through the undo tree by state count or by time, see `history::parse_travel`
+    pub fn time_travel(self: &mut Self, travel: Travel) {
+        match self.history.travel(travel) {
+            Some(path) => {
+                self.apply_edits(&path.edits, path.cursor);
+                let state = self.history.current_state();
+                self.set_status(format!("State {}", state));
+            }
+            None => self.set_status(String::from("Nothing to travel to")),
+        }
+    }
+
+    pub fn list_branches(self: &mut Self) {
+        let now = SystemTime::now();
+        let branches = self
+            .history
+            .branches()
+            .iter()
+            .map(|branch| {
+                let age = now.duration_since(branch.time).unwrap_or_default();
+                format!(
+                    "{}#{} ({} changes, {}s ago)",
+                    if branch.current { "*" } else { "" },
+                    branch.state,
+                    branch.changes,
+                    age.as_secs()
+                )
+            })
+            .collect::<Vec<String>>();
+
+        self.set_status(format!("Branches: {}", branches.join(", ")));
+    }
+
+    //Replays edits on the buffer without recording them, leaving the cursor at `cursor`
+    fn apply_edits(self: &mut Self, edits: &[Edit], cursor: usize) {
+        self.anchor = None;
+        //History loaded from disk may not fit the buffer, stop instead of panicking
+        let mut failed = None;
+        for edit in edits {
+            let result = match edit.kind {
+                EditKind::Insert => self
+                    .buffer
+                    .try_insert_str(edit.index, &edit.text)
+                    .map(|_| (0, edit.text.len())),
+                EditKind::Delete => self
+                    .buffer
+                    .try_delete_range(edit.index..edit.index + edit.text.len())
+                    .map(|_| (edit.text.len(), 0)),
+            };
+
+            match result {
+                Ok((old_len, new_len)) => self.update_line_map(edit.index, old_len, new_len),
+                Err(err) => {
+                    failed = Some(err);
+                    break;
+                }
+            }
+        }
+        //Undo and redo can lead back to the text on disk
+        if self.saved_state == Some(self.history.current_state()) {
+            self.swap_dirty = true;
+            if self.modified {
+                self.modified = false;
+                self.draw_bars();
+            }
         } else {
-            let new_cursor = self.get_cursor_from_index(curr_index - 1);
-            self.cursor.row = new_cursor.row;
-            self.cursor.column = new_cursor.column;
+            self.set_modified();
+        }
+        if let Some(err) = failed {
+            log::error!("Edit history does not match the buffer: {}", err);
+            self.status = format!("Edit history does not match the buffer: {}", err);
+            self.draw_bars();
         }
 
+        self.set_cursor_index(cursor);
         self.draw_lines(0);
         self.move_to_cursor();
     }
 
+    //Moves the cursor to a buffer index, scrolling so that its line is visible
+    fn set_cursor_index(self: &mut Self, index: usize) {
+        let index = cmp::min(index, self.buffer.len());
+        let line_index = self.get_index_line(index);
+        let max_rows = self.editor_dim.max_rows as usize;
+        if line_index < self.line_offset {
+            self.line_offset = line_index;
+        } else if line_index >= self.line_offset + max_rows {
+            self.line_offset = line_index + 1 - max_rows;
+        }
+
+        let cursor = self.get_cursor_from_index(index);
+        self.cursor.row = cursor.row;
+        self.cursor.column = cursor.column;
+    }
+
     pub fn resize_redraw(self: &mut Self, window_dim: ScreenDimensions) {
         let index = self.get_current_index();
+        let old_cols = self.editor_dim.max_cols;
 
         self.window_dim.row = window_dim.row;
         self.window_dim.column = window_dim.column;
@@ -545,19 +1467,28 @@ impl Editor {
         self.editor_dim.max_rows = window_dim.max_rows - 4;
         self.editor_dim.max_cols = window_dim.max_cols - 4;
 
-        self.line_map = self.get_line_map(self.editor_dim.max_cols);
-        if self.line_offset > 0
-            && self.line_map.len() - self.line_offset < self.editor_dim.max_rows as usize
-        {
-            let value = self.line_map.len() as isize - self.editor_dim.max_rows as isize - 1;
-            self.line_offset = if value < 0 { 0 } else { value as usize };
+        //Only a new width changes where lines wrap
+        if self.editor_dim.max_cols != old_cols {
+            self.line_map = self.wrap_all();
         }
+        //Fewer lines after rewrapping must not leave empty rows below the last one
+        let max_offset = self.line_map.len().saturating_sub(self.editor_dim.max_rows as usize);
+        self.line_offset = cmp::min(self.line_offset, max_offset);
+        self.set_cursor_index(index);
+
+        self.screen.resize(window_dim.max_cols, window_dim.max_rows);
+        self.backend.resize(window_dim.max_cols, window_dim.max_rows);
         self.draw_window();
         self.draw_lines(0);
-
-        let cursor = self.get_cursor_from_index(index);
-        self.cursor.row = cursor.row;
-        self.cursor.column = cursor.column;
         self.move_to_cursor();
     }
 }
+
+//Screen columns taken by `ch`, tabs are expanded to `TAB_WIDTH` spaces
+fn char_width(ch: char) -> u16 {
+    if ch == '\t' {
+        TAB_WIDTH
+    } else {
+        screen::display_width(ch)
+    }
+}
@@ -0,0 +1,127 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+//Writes `contents` to `path` without ever leaving a half-written file behind.
+//The data goes into a temporary file in the same directory which is synced and then
+//renamed over the original, so readers only ever see the old or the new contents.
+pub fn write_atomic(path: &Path, contents: &[u8], backup: bool) -> std::io::Result<()> {
+    write_atomic_like(path, contents, backup, None)
+}
+
+//Like `write_atomic`, but the file gets the permissions and owner of `like` instead of the ones
+//of the file it replaces, when `like` exists
+pub fn write_atomic_like(
+    path: &Path,
+    contents: &[u8],
+    backup: bool,
+    like: Option<&Path>,
+) -> std::io::Result<()> {
+    //Write through symlinks instead of replacing the link itself
+    let path = match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(err) if err.kind() == ErrorKind::NotFound => path.to_path_buf(),
+        Err(err) => return Err(err),
+    };
+
+    let original = metadata(&path)?;
+    let template = match like {
+        Some(like) => metadata(like)?.or_else(|| original.clone()),
+        None => original.clone(),
+    };
+
+    let tmp_path = sibling_path(&path, |name| format!(".{}.{}.tmp", name, std::process::id()))?;
+    let result = write_tmp(&tmp_path, contents, template.as_ref());
+    if let Err(err) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+
+    if backup && original.is_some() {
+        let backup_path = sibling_path(&path, |name| format!("{}~", name))?;
+        if let Err(err) = fs::copy(&path, backup_path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+    }
+
+    if let Err(err) = fs::rename(&tmp_path, &path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+
+    sync_dir(&path)
+}
+
+fn metadata(path: &Path) -> std::io::Result<Option<fs::Metadata>> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn write_tmp(tmp_path: &Path, contents: &[u8], original: Option<&fs::Metadata>) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(tmp_path)?;
+
+    //Permissions go first so the contents are never readable by more people than they should be
+    if let Some(metadata) = original {
+        file.set_permissions(metadata.permissions())?;
+        copy_owner(&file, metadata)?;
+    }
+
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+#[cfg(unix)]
+fn copy_owner(file: &File, metadata: &fs::Metadata) -> std::io::Result<()> {
+    use std::os::unix::fs::{fchown, MetadataExt};
+
+    //Only root can give files away, keeping the group is still worth a try for everyone else
+    match fchown(file, Some(metadata.uid()), Some(metadata.gid())) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+            let _ = fchown(file, None, Some(metadata.gid()));
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_file: &File, _metadata: &fs::Metadata) -> std::io::Result<()> {
+    Ok(())
+}
+
+//Makes the rename itself durable
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+//Builds a path next to `path` whose file name is derived from the original one
+pub fn sibling_path(path: &Path, name_fn: impl Fn(&str) -> String) -> std::io::Result<PathBuf> {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "sibling_path: Path has no file name",
+            ))
+        }
+    };
+
+    Ok(path.with_file_name(name_fn(&name)))
+}
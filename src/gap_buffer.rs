@@ -1,79 +1,125 @@
-use std::io::{Error, ErrorKind};
-use std::{ptr::NonNull, alloc::Layout};
+use std::{fmt, ptr::NonNull, alloc::Layout};
 
-use std::{alloc, ptr};
+use std::{alloc, cmp, ptr};
+use std::ops::Range;
+
+use crate::line_index::LineIndex;
+use crate::storage::{utf8_len, BufferError, CharsIter, TextStorage};
 
 pub struct GapWindow {
     pub index: usize,
     pub window_size: usize
 }
 
+//Gap buffer over UTF-8 bytes, every position taken or returned is a byte offset
+//into the text and has to sit on a char boundary.
+//
+//Every mutating method has a `try_` variant that reports misuse as a `BufferError`,
+//the plain ones panic on the same errors. Deleting past either end of the text is no misuse
+//for `delete_ch` and `delete_next_ch` though, there they do nothing.
 pub struct TextGapBuffer {
     window_size: usize,
 
-    ptr: NonNull<char>,
+    ptr: NonNull<u8>,
     gap_window: GapWindow,
     size: usize,
-    cap: usize
+    cap: usize,
+    lines: LineIndex
 }
 
 impl TextGapBuffer {
+    #[allow(dead_code)]
     pub fn new () -> Self {
         TextGapBuffer::new_in(1200, 100)
     }
 
+    #[allow(dead_code)]
     pub fn new_in (initial_cap: usize, window_size: usize) -> Self {
-        let layout = match Layout::array::<char>(initial_cap) {
+        TextGapBuffer::try_new_in(initial_cap, window_size).unwrap()
+    }
+
+    pub fn try_new_in (initial_cap: usize, window_size: usize) -> Result<Self, BufferError> {
+        let initial_cap = cmp::max(initial_cap, 1);
+        let layout = match Layout::array::<u8>(initial_cap) {
             Ok(layout) => layout,
-            Err(_) => panic!("Error")
+            Err(_) => return Err(BufferError::AllocationFailure(initial_cap))
         };
 
         let ptr = unsafe { alloc::alloc(layout) };
-        let ptr = match NonNull::new(ptr as *mut char) {
+        let ptr = match NonNull::new(ptr) {
             Some(ptr) => ptr,
-            None => alloc::handle_alloc_error(layout)
+            None => return Err(BufferError::AllocationFailure(initial_cap))
         };
 
         let gap_window = GapWindow {
             index: 0,
-            window_size
+            window_size: cmp::min(window_size, initial_cap)
         };
 
-        TextGapBuffer {
+        Ok(TextGapBuffer {
             window_size,
 
             ptr,
             gap_window,
             size: 0,
-            cap: initial_cap
+            cap: initial_cap,
+            lines: LineIndex::new()
+        })
+    }
+
+    //Loads the whole text with a single copy, the gap is left at the end
+    pub fn from_text (text: &str) -> Self {
+        TextGapBuffer::try_from_text(text).unwrap()
+    }
+
+    pub fn try_from_text (text: &str) -> Result<Self, BufferError> {
+        let window_size = 100;
+        let mut gap_buffer = TextGapBuffer::try_new_in(text.len() + window_size, window_size)?;
+        unsafe {
+            ptr::copy_nonoverlapping(text.as_ptr(), gap_buffer.ptr.as_ptr(), text.len());
         }
+
+        gap_buffer.size = text.len();
+        gap_buffer.gap_window.index = text.len();
+        gap_buffer.lines = LineIndex::from_text(text);
+        Ok(gap_buffer)
     }
 
-    fn grow (self: &mut Self) {
-        let cap = 2 * self.cap;
-        let layout = match Layout::array::<char>(cap) {
+    fn try_grow (self: &mut Self) -> Result<(), BufferError> {
+        let cap = match self.cap.checked_mul(2) {
+            Some(cap) => cap,
+            None => return Err(BufferError::AllocationFailure(usize::MAX))
+        };
+        let layout = match Layout::array::<u8>(cap) {
             Ok(layout) => layout,
-            Err(_) => panic!("Error")
+            Err(_) => return Err(BufferError::AllocationFailure(cap))
         };
 
         let ptr = unsafe {
             alloc::realloc(
-                self.ptr.as_ptr() as *mut u8,
-                Layout::array::<char>(self.cap).unwrap(),
+                self.ptr.as_ptr(),
+                Layout::array::<u8>(self.cap).unwrap(),
                 layout.size()
             )
         };
 
-        self.ptr = match NonNull::new(ptr as *mut char) {
+        //On failure the old allocation is still valid and untouched
+        self.ptr = match NonNull::new(ptr) {
             Some(ptr) => ptr,
-            None => alloc::handle_alloc_error(layout)
+            None => return Err(BufferError::AllocationFailure(cap))
         };
 
         self.cap = cap;
+        Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn move_window (self: &mut Self, pos: usize) {
-        let pos = if pos > self.size { self.size } else { pos };
+        self.try_move_window(pos).unwrap()
+    }
+
+    pub fn try_move_window (self: &mut Self, pos: usize) -> Result<(), BufferError> {
+        self.check_pos(pos)?;
         let window_end_index = self.gap_window.index + self.gap_window.window_size;
 
         if pos < self.gap_window.index {
@@ -99,79 +145,314 @@ impl TextGapBuffer {
         }
 
         self.gap_window.index = pos;
+        Ok(())
     }
 
-    fn resize_window (self: &mut Self) {
-        if self.cap <= self.size + self.window_size {
-            self.grow();
+    //Widens the gap to at least `min_size` bytes, moving the text after it to the end
+    fn try_resize_window (self: &mut Self, min_size: usize) -> Result<(), BufferError> {
+        let window_size = cmp::max(self.window_size, min_size);
+        while self.cap < self.size + window_size {
+            self.try_grow()?;
         }
 
-        let full_size = self.size + self.window_size;
-        let size = full_size - self.gap_window.index;
+        let tail_index = self.gap_window.index + self.gap_window.window_size;
+        let size = self.size - self.gap_window.index;
         unsafe {
             std::ptr::copy(
-                self.ptr.as_ptr().add(self.gap_window.index),
-                self.ptr.as_ptr().add(self.gap_window.index + self.window_size),
+                self.ptr.as_ptr().add(tail_index),
+                self.ptr.as_ptr().add(self.gap_window.index + window_size),
                 size
             );
         }
 
-        self.gap_window.window_size = self.window_size;
+        self.gap_window.window_size = window_size;
+        Ok(())
     }
 
+    //Inserts `ch` right before the gap
+    #[allow(dead_code)]
     pub fn insert_ch (self: &mut Self, ch: char) {
-        if self.cap <= self.size + self.gap_window.window_size {
-            self.grow();
+        self.try_insert_ch(ch).unwrap()
+    }
+
+    #[allow(dead_code)]
+    pub fn try_insert_ch (self: &mut Self, ch: char) -> Result<(), BufferError> {
+        let mut bytes = [0; 4];
+        self.try_insert_str(self.gap_window.index, ch.encode_utf8(&mut bytes))
+    }
+
+    //Inserts `text` at byte `pos`, making room for all of it at once
+    #[allow(dead_code)]
+    pub fn insert_str (self: &mut Self, pos: usize, text: &str) {
+        self.try_insert_str(pos, text).unwrap()
+    }
+
+    pub fn try_insert_str (self: &mut Self, pos: usize, text: &str) -> Result<(), BufferError> {
+        self.try_move_window(pos)?;
+        if self.gap_window.window_size < text.len() {
+            self.try_resize_window(text.len())?;
         }
 
         unsafe {
-            ptr::write(self.ptr.as_ptr().add(self.gap_window.index), ch);
+            ptr::copy_nonoverlapping(
+                text.as_ptr(),
+                self.ptr.as_ptr().add(self.gap_window.index),
+                text.len()
+            );
         }
 
-        self.gap_window.index += 1;
-        self.gap_window.window_size -= 1;
-        self.size += 1;
+        self.gap_window.index += text.len();
+        self.gap_window.window_size -= text.len();
+        self.size += text.len();
+        self.lines.insert(pos, text);
+        Ok(())
+    }
 
-        if self.gap_window.window_size == 0 {
-            self.resize_window();
+    //Deletes the char right before the gap, doing nothing at the start of the text
+    #[allow(dead_code)]
+    pub fn delete_ch (self: &mut Self) {
+        if self.gap_window.index > 0 {
+            self.try_delete_ch().unwrap();
         }
     }
 
-    pub fn delete_ch (self: &mut Self) {
-        self.gap_window.index -= 1;
-        self.gap_window.window_size += 1;
-        self.size -= 1;
+    //Deletes and returns the char right before the gap
+    #[allow(dead_code)]
+    pub fn try_delete_ch (self: &mut Self) -> Result<char, BufferError> {
+        if self.gap_window.index == 0 {
+            return Err(BufferError::OutOfRange { pos: 0, len: self.size });
+        }
+
+        let index = self.prev_char_boundary(self.gap_window.index);
+        let ch = self.get(index)?;
+        self.try_delete_range(index..self.gap_window.index)?;
+        Ok(ch)
+    }
+
+    //Deletes the char right after the gap, doing nothing at the end of the text
+    #[allow(dead_code)]
+    pub fn delete_next_ch (self: &mut Self) {
+        if self.gap_window.index < self.size {
+            self.try_delete_next_ch().unwrap();
+        }
+    }
+
+    //Deletes and returns the char right after the gap
+    #[allow(dead_code)]
+    pub fn try_delete_next_ch (self: &mut Self) -> Result<char, BufferError> {
+        let index = self.gap_window.index;
+        let ch = self.get(index)?;
+        self.try_delete_range(index..index + ch.len_utf8())?;
+        Ok(ch)
+    }
+
+    //Deletes the bytes in `range` by widening the gap over them
+    #[allow(dead_code)]
+    pub fn delete_range (self: &mut Self, range: Range<usize>) {
+        self.try_delete_range(range).unwrap()
+    }
+
+    pub fn try_delete_range (self: &mut Self, range: Range<usize>) -> Result<(), BufferError> {
+        self.check_pos(range.start)?;
+        self.check_pos(range.end)?;
+        if range.start >= range.end {
+            return Ok(());
+        }
+
+        self.try_move_window(range.start)?;
+        self.gap_window.window_size += range.end - range.start;
+        self.size -= range.end - range.start;
+        self.lines.delete(range);
+        Ok(())
     }
 
+    //Valid positions are char boundaries from 0 up to and including the length
+    fn check_pos (self: &Self, pos: usize) -> Result<(), BufferError> {
+        if pos > self.size {
+            return Err(BufferError::OutOfRange { pos, len: self.size });
+        }
+
+        if !self.is_char_boundary(pos) {
+            return Err(BufferError::InvalidCharBoundary(pos));
+        }
+
+        Ok(())
+    }
+
+    //Length of the text in bytes
+    #[allow(dead_code)]
     pub fn len (self: &Self) -> usize {
         self.size
     }
 
-    pub fn get (self: &Self, relative_index: usize) -> Result<char, Error> {
-        let index = self.get_absolute_index(relative_index)?;
-        unsafe {
-            Ok(self.ptr.as_ptr().add(index).read())
+    //Char starting at byte `pos`
+    pub fn get (self: &Self, pos: usize) -> Result<char, BufferError> {
+        let first = self.get_byte(pos)?;
+        let len = match utf8_len(first) {
+            Some(len) => len,
+            None => return Err(BufferError::InvalidCharBoundary(pos))
+        };
+
+        let mut bytes = [first, 0, 0, 0];
+        for i in 1..len {
+            bytes[i] = self.get_byte(pos + i)?;
+        }
+
+        //Only whole `&str`s ever go in, so the bytes always decode
+        Ok(std::str::from_utf8(&bytes[..len]).unwrap().chars().next().unwrap())
+    }
+
+    pub fn is_char_boundary (self: &Self, pos: usize) -> bool {
+        match self.get_byte(pos) {
+            Ok(byte) => utf8_len(byte).is_some(),
+            Err(_) => pos == self.size
+        }
+    }
+
+    //Start of the char after the one at `pos`, or the end of the text
+    pub fn next_char_boundary (self: &Self, pos: usize) -> usize {
+        let mut pos = pos;
+        while pos < self.size {
+            pos += 1;
+            if self.is_char_boundary(pos) {
+                break;
+            }
         }
+
+        pos
     }
 
-    //Gets you the relative index of the string
-    fn get_relative_index (self: &Self, absolute_index: usize) -> Result<usize, Error> {
-        if absolute_index >= self.size + self.gap_window.window_size {
-            return Err(Error::new(ErrorKind::OutOfMemory, "get_relative_index: Index out of bound"))
+    //Start of the char before `pos`, or 0
+    pub fn prev_char_boundary (self: &Self, pos: usize) -> usize {
+        let mut pos = cmp::min(pos, self.size);
+        while pos > 0 {
+            pos -= 1;
+            if self.is_char_boundary(pos) {
+                break;
+            }
         }
 
-        let end_window_index = self.gap_window.index + self.gap_window.window_size - 1;
-        if absolute_index > end_window_index {
-            Ok(absolute_index - self.gap_window.window_size)
+        pos
+    }
+
+    //Number of chars in the first `pos` bytes
+    #[allow(dead_code)]
+    pub fn byte_to_char (self: &Self, pos: usize) -> usize {
+        let (left, right) = self.slices();
+        let pos = cmp::min(pos, self.size);
+        if pos <= left.len() {
+            count_chars(&left[..pos])
         } else {
-            Ok(absolute_index)
+            count_chars(left) + count_chars(&right[..pos - left.len()])
+        }
+    }
+
+    //Byte offset of the `char_index`th char, or the end of the text
+    #[allow(dead_code)]
+    pub fn char_to_byte (self: &Self, char_index: usize) -> usize {
+        let (left, right) = self.slices();
+        let mut chars = 0;
+        for (i, byte) in left.iter().chain(right.iter()).enumerate() {
+            if utf8_len(*byte).is_some() {
+                if chars == char_index {
+                    return i;
+                }
+                chars += 1;
+            }
+        }
+
+        self.size
+    }
+
+    //Number of logical lines, a trailing newline starts one more empty line
+    pub fn line_count (self: &Self) -> usize {
+        self.lines.line_count()
+    }
+
+    //Byte offset where logical line `line` starts, or the end of the text
+    pub fn line_to_byte (self: &Self, line: usize) -> usize {
+        self.lines.line_start(line)
+    }
+
+    //Logical line containing byte `pos`
+    pub fn byte_to_line (self: &Self, pos: usize) -> usize {
+        self.lines.line_of(cmp::min(pos, self.size))
+    }
+
+    //The text in `range` as the parts before and after the gap, either may be empty
+    pub fn slice (self: &Self, range: Range<usize>) -> (&str, &str) {
+        self.try_slice(range).unwrap()
+    }
+
+    //A range ending before it starts is out of range, like with `str` slicing
+    pub fn try_slice (self: &Self, range: Range<usize>) -> Result<(&str, &str), BufferError> {
+        self.check_pos(range.start)?;
+        self.check_pos(range.end)?;
+        if range.start > range.end {
+            return Err(BufferError::OutOfRange { pos: range.start, len: range.end });
+        }
+
+        let (left, right) = self.slices();
+        let gap = left.len();
+        let first = &left[cmp::min(range.start, gap)..cmp::min(range.end, gap)];
+        let second = &right[range.start.saturating_sub(gap)..range.end.saturating_sub(gap)];
+
+        //Both ends were checked to be char boundaries and the gap always sits on one
+        unsafe {
+            Ok((std::str::from_utf8_unchecked(first), std::str::from_utf8_unchecked(second)))
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn chars (self: &Self) -> Chars<'_> {
+        self.chars_at(0)
+    }
+
+    //Chars from byte `pos` to the end, call `rev()` to walk backwards from the end instead
+    #[allow(dead_code)]
+    pub fn chars_at (self: &Self, pos: usize) -> Chars<'_> {
+        let (first, second) = self.slice(pos..self.size);
+        Chars {
+            first: first.chars(),
+            second: second.chars()
+        }
+    }
+
+    //Chars before byte `pos`, nearest first
+    #[allow(dead_code)]
+    pub fn chars_rev_at (self: &Self, pos: usize) -> std::iter::Rev<Chars<'_>> {
+        let (first, second) = self.slice(0..pos);
+        Chars {
+            first: first.chars(),
+            second: second.chars()
+        }.rev()
+    }
+
+    //The text before and after the gap
+    fn slices (self: &Self) -> (&[u8], &[u8]) {
+        let tail_index = self.gap_window.index + self.gap_window.window_size;
+        unsafe {
+            (
+                std::slice::from_raw_parts(self.ptr.as_ptr(), self.gap_window.index),
+                std::slice::from_raw_parts(
+                    self.ptr.as_ptr().add(tail_index),
+                    self.size - self.gap_window.index
+                )
+            )
+        }
+    }
+
+    fn get_byte (self: &Self, pos: usize) -> Result<u8, BufferError> {
+        let index = self.get_absolute_index(pos)?;
+        unsafe {
+            Ok(self.ptr.as_ptr().add(index).read())
         }
     }
 
     //Gets you the absolute index of the array with the gap
-    fn get_absolute_index (self: &Self, relative_index: usize) -> Result<usize, Error> {
+    fn get_absolute_index (self: &Self, relative_index: usize) -> Result<usize, BufferError> {
         if relative_index >= self.size {
-            return Err(Error::new(ErrorKind::OutOfMemory, "get_absolute_index: Index out of bound"))
+            return Err(BufferError::OutOfRange { pos: relative_index, len: self.size })
         }
 
         if relative_index >= self.gap_window.index {
@@ -182,15 +463,114 @@ impl TextGapBuffer {
     }
 }
 
+#[allow(dead_code)]
+fn count_chars (bytes: &[u8]) -> usize {
+    bytes.iter().filter(|byte| utf8_len(**byte).is_some()).count()
+}
+
 impl Drop for TextGapBuffer {
     fn drop (self: &mut Self) {
         if self.cap != 0 {
-            println!("==> Freeing TextGapBuffer");
-            let layout = Layout::array::<char>(self.cap).unwrap();
+            let layout = Layout::array::<u8>(self.cap).unwrap();
             unsafe {
-                alloc::dealloc(self.ptr.as_ptr() as *mut u8, layout);
+                alloc::dealloc(self.ptr.as_ptr(), layout);
             }
-            println!("==> Freed TextGapBuffer");
         }
     }
 }
+
+//Iterator over the chars of a `TextGapBuffer`, seamlessly stepping over the gap
+pub struct Chars<'a> {
+    first: std::str::Chars<'a>,
+    second: std::str::Chars<'a>
+}
+
+impl Iterator for Chars<'_> {
+    type Item = char;
+
+    fn next (self: &mut Self) -> Option<char> {
+        self.first.next().or_else(|| self.second.next())
+    }
+}
+
+impl DoubleEndedIterator for Chars<'_> {
+    fn next_back (self: &mut Self) -> Option<char> {
+        self.second.next_back().or_else(|| self.first.next_back())
+    }
+}
+
+impl fmt::Display for TextGapBuffer {
+    fn fmt (self: &Self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (first, second) = self.slice(0..self.size);
+        f.write_str(first)?;
+        f.write_str(second)
+    }
+}
+
+impl From<&str> for TextGapBuffer {
+    fn from (text: &str) -> Self {
+        TextGapBuffer::from_text(text)
+    }
+}
+
+impl TextStorage for TextGapBuffer {
+    fn len (self: &Self) -> usize {
+        self.size
+    }
+
+    fn get (self: &Self, pos: usize) -> Result<char, BufferError> {
+        TextGapBuffer::get(self, pos)
+    }
+
+    fn is_char_boundary (self: &Self, pos: usize) -> bool {
+        TextGapBuffer::is_char_boundary(self, pos)
+    }
+
+    fn try_insert_str (self: &mut Self, pos: usize, text: &str) -> Result<(), BufferError> {
+        TextGapBuffer::try_insert_str(self, pos, text)
+    }
+
+    fn try_delete_range (self: &mut Self, range: Range<usize>) -> Result<(), BufferError> {
+        TextGapBuffer::try_delete_range(self, range)
+    }
+
+    fn chars_in (self: &Self, range: Range<usize>) -> CharsIter<'_> {
+        let (first, second) = self.slice(range);
+        Box::new(Chars {
+            first: first.chars(),
+            second: second.chars()
+        })
+    }
+
+    fn text (self: &Self) -> String {
+        self.to_string()
+    }
+
+    fn next_char_boundary (self: &Self, pos: usize) -> usize {
+        TextGapBuffer::next_char_boundary(self, pos)
+    }
+
+    fn prev_char_boundary (self: &Self, pos: usize) -> usize {
+        TextGapBuffer::prev_char_boundary(self, pos)
+    }
+
+    fn byte_to_char (self: &Self, pos: usize) -> usize {
+        TextGapBuffer::byte_to_char(self, pos)
+    }
+
+    fn char_to_byte (self: &Self, char_index: usize) -> usize {
+        TextGapBuffer::char_to_byte(self, char_index)
+    }
+
+    fn line_count (self: &Self) -> usize {
+        TextGapBuffer::line_count(self)
+    }
+
+    fn line_to_byte (self: &Self, line: usize) -> usize {
+        TextGapBuffer::line_to_byte(self, line)
+    }
+
+    fn byte_to_line (self: &Self, pos: usize) -> usize {
+        TextGapBuffer::byte_to_line(self, pos)
+    }
+}
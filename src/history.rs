@@ -0,0 +1,583 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+//Typing pauses longer than this start a new undo step
+const GROUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum EditKind {
+    Insert,
+    Delete,
+}
+
+//A single change to the buffer, `index` is the byte offset where `text` starts
+#[derive(Clone)]
+pub struct Edit {
+    pub kind: EditKind,
+    pub index: usize,
+    pub text: String,
+}
+
+impl Edit {
+    //The edit that undoes this one
+    pub fn inverse(self: &Self) -> Edit {
+        Edit {
+            kind: match self.kind {
+                EditKind::Insert => EditKind::Delete,
+                EditKind::Delete => EditKind::Insert,
+            },
+            index: self.index,
+            text: self.text.clone(),
+        }
+    }
+
+    fn end(self: &Self) -> usize {
+        self.index + self.text.len()
+    }
+}
+
+//Everything undone or redone by a single keystroke
+pub struct UndoStep {
+    pub edits: Vec<Edit>,
+    pub cursor_before: usize,
+    pub cursor_after: usize,
+}
+
+//A state of the buffer, reached from its parent by applying `step`.
+//Node 0 is the root, the buffer as it was when the history started.
+struct Node {
+    parent: usize,
+    children: Vec<usize>,
+    //Child followed by redo, the most recently visited branch
+    redo_child: Option<usize>,
+    step: UndoStep,
+    time: SystemTime,
+}
+
+//Where to move in the history, see `parse_travel`
+#[derive(Clone, Copy)]
+pub enum Travel {
+    //Relative number of states in the order they were created
+    States(isize),
+    //Relative amount of seconds
+    Seconds(i64),
+    //Absolute state number
+    State(usize),
+}
+
+//The edits taking the buffer from one state to another
+pub struct TravelPath {
+    pub edits: Vec<Edit>,
+    pub cursor: usize,
+}
+
+//Tip of a branch in the undo tree
+pub struct BranchInfo {
+    pub state: usize,
+    pub changes: usize,
+    pub time: SystemTime,
+    pub current: bool,
+}
+
+//Edit history kept as a tree, undoing and then editing starts a new branch
+//instead of throwing the undone states away.
+pub struct History {
+    nodes: Vec<Node>,
+    current: usize,
+    grouping: bool,
+    //Between `begin_step` and `end_step`, with the node edits are added to once there is one
+    joining: bool,
+    joined: Option<usize>,
+    last_edit: Instant,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            nodes: vec![Node {
+                parent: 0,
+                children: Vec::new(),
+                redo_child: None,
+                step: UndoStep {
+                    edits: Vec::new(),
+                    cursor_before: 0,
+                    cursor_after: 0,
+                },
+                time: SystemTime::now(),
+            }],
+            current: 0,
+            grouping: false,
+            joining: false,
+            joined: None,
+            last_edit: Instant::now(),
+        }
+    }
+
+    //Records `text` inserted at `index`, merging it into the current step while typing continues
+    pub fn record_insert(self: &mut Self, index: usize, text: &str, cursor_before: usize) {
+        let cursor_after = index + text.len();
+        if self.can_group() && !text.contains('\n') {
+            let node = &mut self.nodes[self.current];
+            let last = node.step.edits.last_mut().unwrap();
+            if last.kind == EditKind::Insert && last.end() == index && !last.text.ends_with('\n') {
+                last.text.push_str(text);
+                node.step.cursor_after = cursor_after;
+                self.touch();
+                return;
+            }
+        }
+
+        self.push(
+            Edit {
+                kind: EditKind::Insert,
+                index,
+                text: String::from(text),
+            },
+            cursor_before,
+            cursor_after,
+        );
+    }
+
+    //Records `text` deleted from `index`, merging runs of backspaces or forward deletes
+    pub fn record_delete(self: &mut Self, index: usize, text: &str, cursor_before: usize) {
+        if self.can_group() {
+            let node = &mut self.nodes[self.current];
+            let last = node.step.edits.last_mut().unwrap();
+            if last.kind == EditKind::Delete && index + text.len() == last.index {
+                last.text.insert_str(0, text);
+                last.index = index;
+                node.step.cursor_after = index;
+                self.touch();
+                return;
+            } else if last.kind == EditKind::Delete && index == last.index {
+                last.text.push_str(text);
+                node.step.cursor_after = index;
+                self.touch();
+                return;
+            }
+        }
+
+        self.push(
+            Edit {
+                kind: EditKind::Delete,
+                index,
+                text: String::from(text),
+            },
+            cursor_before,
+            index,
+        );
+    }
+
+    //Records `deleted` at `index` replaced with `inserted` as a new step, either may be empty.
+    //Typing right after replacing a selection is still part of it.
+    pub fn record_replace(
+        self: &mut Self,
+        index: usize,
+        deleted: &str,
+        inserted: &str,
+        cursor_before: usize,
+    ) {
+        let mut edits = Vec::new();
+        if !deleted.is_empty() {
+            edits.push(Edit {
+                kind: EditKind::Delete,
+                index,
+                text: String::from(deleted),
+            });
+        }
+        if !inserted.is_empty() {
+            edits.push(Edit {
+                kind: EditKind::Insert,
+                index,
+                text: String::from(inserted),
+            });
+        }
+        if edits.is_empty() {
+            return;
+        }
+
+        self.seal();
+        let mut edits = edits.into_iter();
+        self.push(edits.next().unwrap(), cursor_before, index + inserted.len());
+        self.nodes[self.current].step.edits.extend(edits);
+    }
+
+    //Ends the current undo step, the next edit starts a new one unless the step is joined
+    pub fn seal(self: &mut Self) {
+        self.grouping = false;
+    }
+
+    //Everything recorded until `end_step` is undone in one go, seals in between included
+    pub fn begin_step(self: &mut Self) {
+        self.seal();
+        self.joining = true;
+        self.joined = None;
+    }
+
+    pub fn end_step(self: &mut Self) {
+        self.seal();
+        self.joining = false;
+        self.joined = None;
+    }
+
+    //Makes the next edit start a new undo step, also between `begin_step` and `end_step`
+    pub fn break_step(self: &mut Self) {
+        self.seal();
+        self.joined = None;
+    }
+
+    pub fn undo(self: &mut Self) -> Option<TravelPath> {
+        if self.current == 0 {
+            return None;
+        }
+
+        let parent = self.nodes[self.current].parent;
+        self.travel_to(parent)
+    }
+
+    pub fn redo(self: &mut Self) -> Option<TravelPath> {
+        let child = self.nodes[self.current].redo_child?;
+        self.travel_to(child)
+    }
+
+    //Moves to another state anywhere in the tree, going up to the common ancestor and back down
+    pub fn travel(self: &mut Self, travel: Travel) -> Option<TravelPath> {
+        let target = match travel {
+            Travel::State(state) => clamp_state(state as i64, self.nodes.len() - 1),
+            Travel::States(count) => {
+                clamp_state((self.current as i64).saturating_add(count as i64), self.nodes.len() - 1)
+            }
+            Travel::Seconds(seconds) => {
+                let now = self.nodes[self.current].time;
+                let target_time = if seconds < 0 {
+                    now.checked_sub(Duration::from_secs(seconds.unsigned_abs()))
+                } else {
+                    now.checked_add(Duration::from_secs(seconds as u64))
+                };
+
+                //Nodes are created in time order, pick the newest one that already existed then
+                match target_time {
+                    Some(target_time) => (0..self.nodes.len())
+                        .rev()
+                        .find(|&i| self.nodes[i].time <= target_time)
+                        .unwrap_or(0),
+                    None => 0,
+                }
+            }
+        };
+
+        if target == self.current {
+            return None;
+        }
+
+        self.travel_to(target)
+    }
+
+    pub fn current_state(self: &Self) -> usize {
+        self.current
+    }
+
+    pub fn branches(self: &Self) -> Vec<BranchInfo> {
+        let mut branches = Vec::new();
+        for i in 0..self.nodes.len() {
+            if !self.nodes[i].children.is_empty() || (i == 0 && self.nodes.len() > 1) {
+                continue;
+            }
+
+            let mut changes = 0;
+            let mut node = i;
+            while node != 0 {
+                changes += 1;
+                node = self.nodes[node].parent;
+            }
+
+            branches.push(BranchInfo {
+                state: i,
+                changes,
+                time: self.nodes[i].time,
+                current: self.is_ancestor(self.current, i),
+            });
+        }
+
+        branches
+    }
+
+    //Line based dump of the whole tree, read back by `History::from_text`
+    pub fn to_text(self: &Self) -> String {
+        let mut text = format!("current {}\n", self.current);
+        for node in &self.nodes {
+            let time = node.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            text.push_str(&format!(
+                "node {} {} {}.{} {} {} {}\n",
+                node.parent,
+                match node.redo_child {
+                    Some(child) => child.to_string(),
+                    None => String::from("-"),
+                },
+                time.as_secs(),
+                time.subsec_nanos(),
+                node.step.cursor_before,
+                node.step.cursor_after,
+                node.step.edits.len()
+            ));
+
+            for edit in &node.step.edits {
+                text.push_str(&format!(
+                    "edit {} {} {}\n",
+                    match edit.kind {
+                        EditKind::Insert => "i",
+                        EditKind::Delete => "d",
+                    },
+                    edit.index,
+                    escape(&edit.text)
+                ));
+            }
+        }
+
+        text
+    }
+
+    pub fn from_text(text: &str) -> Option<History> {
+        let mut lines = text.lines();
+        let current = lines.next()?.strip_prefix("current ")?.parse::<usize>().ok()?;
+
+        let mut nodes: Vec<Node> = Vec::new();
+        while let Some(line) = lines.next() {
+            let fields = line.strip_prefix("node ")?.split(' ').collect::<Vec<&str>>();
+            if fields.len() != 6 {
+                return None;
+            }
+
+            let (secs, nanos) = fields[2].split_once('.')?;
+            let time = UNIX_EPOCH
+                + Duration::new(secs.parse::<u64>().ok()?, nanos.parse::<u32>().ok()?);
+
+            let mut edits = Vec::new();
+            for _ in 0..fields[5].parse::<usize>().ok()? {
+                let mut edit = lines.next()?.strip_prefix("edit ")?.splitn(3, ' ');
+                let kind = match edit.next()? {
+                    "i" => EditKind::Insert,
+                    "d" => EditKind::Delete,
+                    _ => return None,
+                };
+                let index = edit.next()?.parse::<usize>().ok()?;
+                let text = unescape(edit.next()?)?;
+                edits.push(Edit { kind, index, text });
+            }
+
+            nodes.push(Node {
+                parent: fields[0].parse::<usize>().ok()?,
+                children: Vec::new(),
+                redo_child: match fields[1] {
+                    "-" => None,
+                    child => Some(child.parse::<usize>().ok()?),
+                },
+                step: UndoStep {
+                    edits,
+                    cursor_before: fields[3].parse::<usize>().ok()?,
+                    cursor_after: fields[4].parse::<usize>().ok()?,
+                },
+                time,
+            });
+        }
+
+        //Every node but the root hangs off an older one, anything else is a corrupt file
+        if nodes.is_empty() || current >= nodes.len() {
+            return None;
+        }
+        for i in 1..nodes.len() {
+            let parent = nodes[i].parent;
+            if parent >= i {
+                return None;
+            }
+            nodes[parent].children.push(i);
+        }
+        for node in &nodes {
+            if let Some(child) = node.redo_child {
+                if !node.children.contains(&child) {
+                    return None;
+                }
+            }
+        }
+
+        Some(History {
+            nodes,
+            current,
+            grouping: false,
+            joining: false,
+            joined: None,
+            last_edit: Instant::now(),
+        })
+    }
+
+    fn travel_to(self: &mut Self, target: usize) -> Option<TravelPath> {
+        self.seal();
+
+        let mut on_path = vec![false; self.nodes.len()];
+        let mut node = self.current;
+        on_path[node] = true;
+        while node != 0 {
+            node = self.nodes[node].parent;
+            on_path[node] = true;
+        }
+
+        let mut down = Vec::new();
+        let mut ancestor = target;
+        while !on_path[ancestor] {
+            down.push(ancestor);
+            ancestor = self.nodes[ancestor].parent;
+        }
+
+        let mut edits = Vec::new();
+        let mut cursor = self.nodes[self.current].step.cursor_before;
+        let mut node = self.current;
+        while node != ancestor {
+            let step = &self.nodes[node].step;
+            edits.extend(step.edits.iter().rev().map(|edit| edit.inverse()));
+            cursor = step.cursor_before;
+            node = self.nodes[node].parent;
+        }
+
+        for &node in down.iter().rev() {
+            let step = &self.nodes[node].step;
+            edits.extend(step.edits.iter().cloned());
+            cursor = step.cursor_after;
+
+            let parent = self.nodes[node].parent;
+            self.nodes[parent].redo_child = Some(node);
+        }
+
+        self.current = target;
+        Some(TravelPath { edits, cursor })
+    }
+
+    fn is_ancestor(self: &Self, ancestor: usize, node: usize) -> bool {
+        let mut node = node;
+        loop {
+            if node == ancestor {
+                return true;
+            }
+            if node == 0 {
+                return false;
+            }
+            node = self.nodes[node].parent;
+        }
+    }
+
+    fn can_group(self: &Self) -> bool {
+        self.grouping
+            && self.last_edit.elapsed() < GROUP_TIMEOUT
+            && self.current != 0
+            && self.nodes[self.current].children.is_empty()
+    }
+
+    fn push(self: &mut Self, edit: Edit, cursor_before: usize, cursor_after: usize) {
+        //Joined to the step being built as long as nothing was undone or branched off from it
+        if self.joined == Some(self.current) && self.nodes[self.current].children.is_empty() {
+            let step = &mut self.nodes[self.current].step;
+            step.edits.push(edit);
+            step.cursor_after = cursor_after;
+            self.grouping = true;
+            self.touch();
+            return;
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            parent: self.current,
+            children: Vec::new(),
+            redo_child: None,
+            step: UndoStep {
+                edits: vec![edit],
+                cursor_before,
+                cursor_after,
+            },
+            time: SystemTime::now(),
+        });
+
+        let parent = &mut self.nodes[self.current];
+        parent.children.push(index);
+        parent.redo_child = Some(index);
+
+        self.current = index;
+        self.grouping = true;
+        if self.joining {
+            self.joined = Some(index);
+        }
+        self.touch();
+    }
+
+    fn touch(self: &mut Self) {
+        self.last_edit = Instant::now();
+        self.nodes[self.current].time = SystemTime::now();
+    }
+}
+
+fn clamp_state(value: i64, max: usize) -> usize {
+    value.clamp(0, max as i64) as usize
+}
+
+//Parses a time travel request: `-5m` / `+30s` / `-2h` move by time, `-3` / `+3` move by states
+//and a bare `12` jumps to state 12.
+pub fn parse_travel(spec: &str) -> Option<Travel> {
+    let spec = spec.trim();
+    let (sign, rest) = match spec.chars().next()? {
+        '-' => (-1, &spec[1..]),
+        '+' => (1, &spec[1..]),
+        _ => return spec.parse::<usize>().ok().map(Travel::State),
+    };
+
+    let unit = match rest.chars().last()? {
+        's' => Some(1),
+        'm' => Some(60),
+        'h' => Some(60 * 60),
+        'd' => Some(24 * 60 * 60),
+        _ => None,
+    };
+
+    match unit {
+        Some(unit) => {
+            //Spans too long to count in seconds are not valid either
+            let value = rest[..rest.len() - 1].parse::<i64>().ok()?;
+            let seconds = value.checked_mul(unit)?.checked_mul(sign)?;
+            Some(Travel::Seconds(seconds))
+        }
+        None => {
+            let value = rest.parse::<isize>().ok()?;
+            Some(Travel::States(value.checked_mul(sign as isize)?))
+        }
+    }
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
+
+pub fn unescape(text: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            unescaped.push(ch);
+            continue;
+        }
+
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            _ => return None,
+        }
+    }
+
+    Some(unescaped)
+}
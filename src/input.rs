@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+use crossterm::event::{self, Event};
+
+//Where the editor gets its events from
+pub trait Input {
+    //Waits up to `timeout` for an event, true when one is ready to be read
+    fn poll(self: &mut Self, timeout: Duration) -> std::io::Result<bool>;
+    fn read(self: &mut Self) -> std::io::Result<Event>;
+
+    //True once no more events will come, a terminal never runs out
+    fn finished(self: &Self) -> bool {
+        false
+    }
+}
+
+pub struct TerminalInput;
+
+impl Input for TerminalInput {
+    fn poll(self: &mut Self, timeout: Duration) -> std::io::Result<bool> {
+        event::poll(timeout)
+    }
+
+    fn read(self: &mut Self) -> std::io::Result<Event> {
+        event::read()
+    }
+}
@@ -0,0 +1,111 @@
+//Start offsets of the logical lines, kept up to date as text is inserted and deleted. Offsets
+//are in bytes like the positions the gap buffer takes, not in chars.
+//
+//Works like the gap buffer itself: starts up to the last edit are stored as they are, the ones
+//after it are stored counted back from the end of the text, so an edit never has to touch them.
+//Only moving the edit point shuffles entries across, lookups are a binary search at most.
+pub struct LineIndex {
+    //Starts at or before the edit point, ascending
+    before: Vec<usize>,
+    //Starts after the edit point as `len - start`, the nearest one last
+    after: Vec<usize>,
+    len: usize,
+}
+
+impl LineIndex {
+    pub fn new() -> Self {
+        LineIndex {
+            before: Vec::new(),
+            after: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn from_text(text: &str) -> Self {
+        LineIndex {
+            before: line_starts(text, 0).collect(),
+            after: Vec::new(),
+            len: text.len(),
+        }
+    }
+
+    //`text` was inserted at byte `pos`
+    pub fn insert(self: &mut Self, pos: usize, text: &str) {
+        self.move_to(pos);
+        self.before.extend(line_starts(text, pos));
+        self.len += text.len();
+    }
+
+    //The bytes in `range` were deleted
+    pub fn delete(self: &mut Self, range: std::ops::Range<usize>) {
+        self.move_to(range.start);
+
+        //A line starting inside the range, or right at its end, lost its newline
+        while let Some(&rel) = self.after.last() {
+            if self.len - rel > range.end {
+                break;
+            }
+            self.after.pop();
+        }
+        self.len -= range.end - range.start;
+    }
+
+    //Number of logical lines, a trailing newline starts one more empty line
+    pub fn line_count(self: &Self) -> usize {
+        self.before.len() + self.after.len() + 1
+    }
+
+    //Byte offset where logical line `line` starts, or the end of the text
+    pub fn line_start(self: &Self, line: usize) -> usize {
+        if line == 0 {
+            return 0;
+        }
+
+        let i = line - 1;
+        if i < self.before.len() {
+            self.before[i]
+        } else if i - self.before.len() < self.after.len() {
+            self.len - self.after[self.after.len() - 1 - (i - self.before.len())]
+        } else {
+            self.len
+        }
+    }
+
+    //Logical line containing byte `pos`
+    pub fn line_of(self: &Self, pos: usize) -> usize {
+        let lines = self.before.partition_point(|start| *start <= pos);
+        if lines < self.before.len() {
+            return lines;
+        }
+
+        //`after` holds `len - start`, so starts up to `pos` are the values from `len - pos` on
+        lines + self.after.len() - self.after.partition_point(|rel| *rel < self.len - pos)
+    }
+
+    //Shifts entries so that `before` holds exactly the starts up to `pos`
+    fn move_to(self: &mut Self, pos: usize) {
+        while let Some(&start) = self.before.last() {
+            if start <= pos {
+                break;
+            }
+            self.before.pop();
+            self.after.push(self.len - start);
+        }
+
+        while let Some(&rel) = self.after.last() {
+            if self.len - rel > pos {
+                break;
+            }
+            self.after.pop();
+            self.before.push(self.len - rel);
+        }
+    }
+}
+
+//Offsets right after each newline in `text`, shifted by `offset`
+fn line_starts(text: &str, offset: usize) -> impl Iterator<Item = usize> + '_ {
+    text.bytes()
+        .enumerate()
+        .filter(|(_, byte)| *byte == b'\n')
+        .map(move |(i, _)| offset + i + 1)
+}
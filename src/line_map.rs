@@ -0,0 +1,84 @@
+use std::ops::Range;
+
+use crate::editor::LineInfo;
+
+//The visual lines of the whole text, in order.
+//
+//Edits only ever replace the rows of the logical lines they touch, so this is laid out like
+//the gap buffer: rows before the last edit keep their byte index as is, rows after it store
+//the index counted back from the end of the text. Everything behind an edit moves along
+//for free and only jumping to another spot shuffles rows across.
+pub struct LineMap {
+    //Rows before the edit point, in order
+    before: Vec<LineInfo>,
+    //Rows after the edit point with `index` as `text_len - index`, the nearest one last
+    after: Vec<LineInfo>,
+    text_len: usize,
+}
+
+impl LineMap {
+    pub fn new(rows: Vec<LineInfo>, text_len: usize) -> Self {
+        LineMap {
+            before: rows,
+            after: Vec::new(),
+            text_len,
+        }
+    }
+
+    pub fn len(self: &Self) -> usize {
+        self.before.len() + self.after.len()
+    }
+
+    pub fn get(self: &Self, row: usize) -> LineInfo {
+        if row < self.before.len() {
+            return self.before[row];
+        }
+
+        let line = self.after[self.after.len() - 1 - (row - self.before.len())];
+        LineInfo {
+            index: self.text_len - line.index,
+            ..line
+        }
+    }
+
+    //Row holding byte `index`, the last one that starts at or before it
+    pub fn find(self: &Self, index: usize) -> usize {
+        let rows = self.before.partition_point(|line| line.index <= index);
+        if rows < self.before.len() {
+            return rows.saturating_sub(1);
+        }
+
+        let rel = self.text_len.saturating_sub(index);
+        let after_rows = self.after.len() - self.after.partition_point(|line| line.index < rel);
+        (rows + after_rows).saturating_sub(1)
+    }
+
+    //Swaps `rows` for `new_rows`, which are laid out for the text after the edit
+    pub fn replace(self: &mut Self, rows: Range<usize>, new_rows: Vec<LineInfo>, text_len: usize) {
+        self.move_to(rows.start);
+        let keep = self.after.len() - (rows.end - rows.start);
+        self.after.truncate(keep);
+
+        self.before.extend(new_rows);
+        self.text_len = text_len;
+    }
+
+    //Shifts rows so that exactly `row` of them come before the edit point
+    fn move_to(self: &mut Self, row: usize) {
+        while self.before.len() > row {
+            let line = self.before.pop().unwrap();
+            self.after.push(LineInfo {
+                index: self.text_len - line.index,
+                ..line
+            });
+        }
+
+        while self.before.len() < row {
+            let line = self.after.pop().unwrap();
+            self.before.push(LineInfo {
+                index: self.text_len - line.index,
+                ..line
+            });
+        }
+    }
+}
@@ -1,13 +1,157 @@
-use std::fs::OpenOptions;
+use std::env;
+use std::fs::{self, File, OpenOptions};
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn log (data: &str) {
-    let mut data_file = OpenOptions::new().append(true)
-        .open("log.txt")
-        .expect("cannot open file");
+use log::{LevelFilter, Log, Metadata, Record};
 
-    let mut line = String::from(data);
-    line.push('\n');
-    data_file.write(line.as_bytes())
-        .expect("write failed");
+//Picks the level, `off` when unset
+pub const LEVEL_VAR: &str = "TEXT_EDITOR_LOG";
+//Picks the file, `log.txt` in the state directory when unset
+pub const FILE_VAR: &str = "TEXT_EDITOR_LOG_FILE";
+
+//A full log file is moved to `FILE.1`, `FILE.1` to `FILE.2` and so on, the oldest is dropped
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+const KEPT_LOGS: usize = 3;
+
+//Where the editor keeps its own files, `$XDG_STATE_HOME/text-editor` or
+//`~/.local/state/text-editor`
+pub fn state_dir() -> PathBuf {
+    let base = match (env::var_os("XDG_STATE_HOME"), env::var_os("HOME")) {
+        (Some(state), _) if !state.is_empty() => PathBuf::from(state),
+        (_, Some(home)) if !home.is_empty() => Path::new(&home).join(".local").join("state"),
+        _ => env::temp_dir(),
+    };
+
+    base.join("text-editor")
+}
+
+//Installs the file logger. The level and path come from the flags if they were given and from
+//the environment otherwise. Naming a file without a level logs at `info`.
+//Gives back the file being written to, or `None` when logging stays off.
+pub fn init(level: Option<&str>, path: Option<PathBuf>) -> std::io::Result<Option<PathBuf>> {
+    let level = level.map(String::from).or_else(|| env::var(LEVEL_VAR).ok());
+    let path = path.or_else(|| env::var_os(FILE_VAR).map(PathBuf::from));
+
+    let level = match (level, &path) {
+        (Some(level), _) => level.parse::<LevelFilter>().map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Unknown log level '{}', expected off, error, warn, info, debug or trace",
+                    level
+                ),
+            )
+        })?,
+        (None, Some(_)) => LevelFilter::Info,
+        (None, None) => LevelFilter::Off,
+    };
+    if level == LevelFilter::Off {
+        return Ok(None);
+    }
+
+    let path = path.unwrap_or_else(|| state_dir().join("log.txt"));
+    let logger = FileLogger::open(&path, level, MAX_LOG_SIZE)?;
+    log::set_boxed_logger(Box::new(logger))
+        .map_err(|err| std::io::Error::other(err.to_string()))?;
+    log::set_max_level(level);
+
+    Ok(Some(path))
+}
+
+struct LogFile {
+    file: File,
+    size: u64,
+}
+
+pub struct FileLogger {
+    path: PathBuf,
+    level: LevelFilter,
+    max_size: u64,
+    file: Mutex<LogFile>,
+}
+
+impl FileLogger {
+    //Appends to the file at `path`, creating it and the directories above it as needed
+    pub fn open(path: &Path, level: LevelFilter, max_size: u64) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        Ok(FileLogger {
+            path: path.to_path_buf(),
+            level,
+            max_size,
+            file: Mutex::new(open_log(path)?),
+        })
+    }
+
+    fn rotate(self: &Self, log_file: &mut LogFile) -> std::io::Result<()> {
+        for i in (1..KEPT_LOGS).rev() {
+            let from = rotated_path(&self.path, i);
+            if from.exists() {
+                fs::rename(from, rotated_path(&self.path, i + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+
+        *log_file = open_log(&self.path)?;
+        Ok(())
+    }
+}
+
+impl Log for FileLogger {
+    fn enabled(self: &Self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(self: &Self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format!(
+            "{}.{:03} {:<5} {}: {}\n",
+            time.as_secs(),
+            time.subsec_millis(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        //Logging must never take the editor down, a line that cannot be written is lost
+        let Ok(mut log_file) = self.file.lock() else {
+            return;
+        };
+        if log_file.size > 0 && log_file.size + line.len() as u64 > self.max_size {
+            let _ = self.rotate(&mut log_file);
+        }
+        if log_file.file.write_all(line.as_bytes()).is_ok() {
+            log_file.size += line.len() as u64;
+        }
+    }
+
+    fn flush(self: &Self) {
+        if let Ok(mut log_file) = self.file.lock() {
+            let _ = log_file.file.flush();
+        }
+    }
+}
+
+fn open_log(path: &Path) -> std::io::Result<LogFile> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok(LogFile { file, size })
+}
+
+//`log.txt` is rotated to `log.txt.1`
+fn rotated_path(path: &Path, i: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", i));
+    PathBuf::from(name)
 }
@@ -1,98 +1,456 @@
+#![allow(
+    clippy::needless_arbitrary_self_type,
+    clippy::needless_return,
+    clippy::needless_range_loop,
+    clippy::upper_case_acronyms
+)]
+
 use std::io::stdout;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::env;
 use std::panic;
 
+mod backend;
+mod buffer;
 mod editor;
+mod file;
 mod logger;
 mod gap_buffer;
+mod line_index;
+mod line_map;
+mod history;
+mod input;
+mod marks;
+mod piece_table;
+mod recording;
+mod registers;
+mod rope;
+mod screen;
+mod storage;
+mod swap;
+mod undo_file;
+mod vim;
+mod words;
+
+#[cfg(test)]
+mod tests;
 
 use crossterm::{
     execute,
     ExecutableCommand,
     terminal::{Clear, ClearType, EnterAlternateScreen, enable_raw_mode, LeaveAlternateScreen, disable_raw_mode, window_size},
-    cursor::{MoveToColumn, MoveToRow, EnableBlinking, DisableBlinking},
-    event::{poll, read, Event, KeyCode}
+    cursor::{EnableBlinking, DisableBlinking},
+    event::{
+        Event, KeyCode, KeyModifiers, MouseButton, MouseEventKind,
+        EnableBracketedPaste, DisableBracketedPaste, EnableMouseCapture, DisableMouseCapture
+    }
 };
-use editor::{ScreenDimensions, Direction};
+use backend::{Backend, CrosstermBackend, MemoryBackend};
+use editor::{Editor, ScreenDimensions, Direction};
+use history::Travel;
+use input::{Input, TerminalInput};
+use recording::{Recorder, Replay};
+use storage::StorageKind;
+use vim::Vim;
 
 fn main() -> std::io::Result<()> {
-    enable_raw_mode()?;
-    env::set_var("RUST_BACKTRACE", "1");
-
-    panic::set_hook(Box::new(|panic_info| {
-        let backtrace = std::backtrace::Backtrace::capture();
-
-        stdout()
-            .execute(DisableBlinking).unwrap()
-            .execute(LeaveAlternateScreen).unwrap();
-        disable_raw_mode().unwrap();
-
-        eprintln!("{}", panic_info);
-        println!("{}", backtrace);
-    }));
-
-    execute!(
-        stdout(),
-        EnterAlternateScreen,
-        Clear(ClearType::All),
-        EnableBlinking
-    )?;
-
-    let mut journal = editor::Editor::new(
-        ScreenDimensions {
-            row: 0,
-            column: 0,
-            max_rows: window_size()?.rows,
-            max_cols: window_size()?.columns
+    let mut file_path = None;
+    let mut backup = false;
+    let mut osc52 = false;
+    let mut word_chars = None;
+    let mut storage = StorageKind::GapBuffer;
+    let mut record_path = None;
+    let mut replay_path = None;
+    let mut headless = false;
+    let mut fast = false;
+    let mut vim = None;
+    let mut log_level = None;
+    let mut log_file = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--backup" => backup = true,
+            "--osc52" => osc52 = true,
+            "--vim" => vim = Some(Vim::new()),
+            "--word-chars" => word_chars = args.next(),
+            "--storage" => {
+                let name = args.next().unwrap_or_default();
+                storage = match StorageKind::parse(&name) {
+                    Some(storage) => storage,
+                    None => {
+                        eprintln!("Unknown storage '{}', expected gap, rope or piece", name);
+                        return Ok(());
+                    }
+                };
+            },
+            "--record" => record_path = args.next(),
+            "--replay" => replay_path = args.next(),
+            "--headless" => headless = true,
+            "--fast" => fast = true,
+            "--log-level" => log_level = args.next(),
+            "--log-file" => log_file = args.next().map(PathBuf::from),
+            _ => file_path = Some(arg)
+        }
+    }
+
+    match logger::init(log_level.as_deref(), log_file) {
+        Ok(Some(path)) => log::info!("Logging to {}", path.display()),
+        Ok(None) => {},
+        Err(err) => {
+            eprintln!("Logging not started: {}", err);
+            return Ok(());
+        }
+    }
+
+    let replay = match &replay_path {
+        Some(path) => Some(Replay::open(Path::new(path), fast)?),
+        None => None
+    };
+    //Without a terminal the window is as large as it was when the events were recorded
+    let headless_size = match (&replay, headless) {
+        (Some(replay), true) => Some(replay.size),
+        (None, true) => {
+            eprintln!("--headless needs a recording to --replay");
+            return Ok(());
+        },
+        _ => None
+    };
+
+    //Errors from here on leave through `?`, the guard puts the terminal back on the way out
+    let terminal = match headless_size {
+        Some(_) => None,
+        None => Some(Terminal::setup()?)
+    };
+
+    let (max_cols, max_rows) = match headless_size {
+        Some(size) => size,
+        None => {
+            let size = window_size()?;
+            (size.columns, size.rows)
+        }
+    };
+    let window_dim = ScreenDimensions {
+        row: 0,
+        column: 0,
+        max_rows,
+        max_cols
+    };
+
+    let screen = MemoryBackend::new(max_cols, max_rows);
+    let backend: Box<dyn Backend> = match headless_size {
+        Some(_) => Box::new(screen.clone()),
+        None => Box::new(CrosstermBackend::new(stdout()))
+    };
+    let mut input: Box<dyn Input> = match replay {
+        Some(replay) => Box::new(replay),
+        None => Box::new(TerminalInput)
+    };
+    if let Some(path) = &record_path {
+        input = Box::new(Recorder::create(Path::new(path), input, (max_cols, max_rows))?);
+    }
+
+    let title = String::from("[Code Journal]");
+    let mut journal = match &file_path {
+        Some(path) => editor::Editor::open(window_dim, title, storage, backend, Path::new(path))?,
+        None => editor::Editor::new(window_dim, title, storage, backend)
+    };
+    journal.set_backup(backup);
+    journal.set_osc52(osc52);
+    if let Some(word_chars) = &word_chars {
+        journal.set_word_chars(word_chars);
+    }
+
+    match journal.find_swap() {
+        Ok(Some(swap_info)) if swap_info.is_stale() => {
+            journal.set_status(String::from("Found a swap file with unsaved changes, recover it? (y/n)"));
+            if confirm(input.as_mut())? {
+                journal.recover_swap(swap_info);
+            } else {
+                journal.discard_swap()?;
+                journal.set_status(String::new());
+            }
+        },
+        Ok(Some(swap_info)) => {
+            journal.disable_swap();
+            journal.set_status(format!("File is being edited by process {}, not journaling", swap_info.pid));
         },
-        String::from("[Code Journal]")
-    );
+        Ok(None) => {},
+        Err(err) => journal.set_status(format!("Unreadable swap file: {}", err))
+    }
 
     loop {
-        if poll(Duration::from_millis(500))? {
-            match read()? {
-                Event::FocusGained => println!("FocusGained"),
-                Event::FocusLost => println!("FocusLost"),
-                Event::Key(event) => {
-                    if event.code == KeyCode::Char('q') {
-                        stdout()
-                            .execute(DisableBlinking)?
-                            .execute(LeaveAlternateScreen)?;
-                        disable_raw_mode()?;
-                        break;
-                    }
+        if input.finished() {
+            break;
+        }
 
-                    match event.code {
-                        KeyCode::Left       => journal.move_cursor(Direction::LEFT, 1),
-                        KeyCode::Down       => journal.move_cursor(Direction::DOWN, 1),
-                        KeyCode::Up         => journal.move_cursor(Direction::UP, 1),
-                        KeyCode::Right      => journal.move_cursor(Direction::RIGHT, 1),
-                        KeyCode::Char(ch)   => journal.insert_ch(ch),
-                        KeyCode::Enter      => journal.insert_ch('\n'),
-                        KeyCode::Tab        => journal.insert_ch('\t'),
-                        KeyCode::Backspace  => journal.delete_ch(),
-                        _ => {}
-                    }
-                },
-                Event::Mouse(event) => println!("{:?}", event),
-                Event::Resize(width, height) => {
-                    let w_size = window_size()?;
-                    //println!("New size {}x{}", width, height);
-                    //println!("Crossterm {}x{} {}x{}", w_size.width, w_size.height, w_size.rows, w_size.columns);
-                    journal.resize_redraw(ScreenDimensions {
-                        row: 0,
-                        column: 0,
-                        max_rows: w_size.rows,
-                        max_cols: w_size.columns
-                    });
-                },
-                _ => println!("Unknown event")
+        if input.poll(Duration::from_millis(500))? {
+            let event = input.read()?;
+            if !handle_event(&mut journal, vim.as_mut(), event, input.as_mut())? {
+                break;
             }
         } else {
             // Timeout expired and no `Event` is available
         }
+
+        if let Err(err) = journal.sync_swap() {
+            log::warn!("Swap file write failed: {}", err);
+            journal.set_status(format!("Swap file write failed: {}", err));
+        }
     };
 
+    match headless_size {
+        //What the screen looks like after the last event, for comparing runs
+        Some(_) => {
+            println!("{}", screen.text());
+            println!("cursor {} {}", screen.cursor().0, screen.cursor().1);
+        },
+        None => drop(terminal)
+    }
+
     Ok(())
 }
+
+//Raw mode, the alternate screen, mouse capture and bracketed paste for as long as it lives
+struct Terminal;
+
+impl Terminal {
+    fn setup() -> std::io::Result<Terminal> {
+        enable_raw_mode()?;
+        let terminal = Terminal;
+        env::set_var("RUST_BACKTRACE", "1");
+
+        panic::set_hook(Box::new(|panic_info| {
+            let backtrace = std::backtrace::Backtrace::capture();
+            restore_terminal();
+
+            log::error!("{}\n{}", panic_info, backtrace);
+            eprintln!("{}", panic_info);
+            println!("{}", backtrace);
+        }));
+
+        execute!(
+            stdout(),
+            EnterAlternateScreen,
+            Clear(ClearType::All),
+            EnableBlinking,
+            //Pastes arrive as one event instead of a key press per char
+            EnableBracketedPaste,
+            EnableMouseCapture
+        )?;
+
+        Ok(terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(self: &mut Self) {
+        restore_terminal();
+    }
+}
+
+fn restore_terminal() {
+    let _ = stdout()
+        .execute(DisableMouseCapture)
+        .and_then(|out| out.execute(DisableBracketedPaste))
+        .and_then(|out| out.execute(DisableBlinking))
+        .and_then(|out| out.execute(LeaveAlternateScreen));
+    let _ = disable_raw_mode();
+}
+
+//Runs the action bound to `event`, false once the editor should quit.
+//Prompts read their answer from `input` as well. Keys go to `vim` first when it is on.
+fn handle_event(
+    journal: &mut Editor,
+    vim: Option<&mut Vim>,
+    event: Event,
+    input: &mut dyn Input
+) -> std::io::Result<bool> {
+    match event {
+        Event::FocusGained => log::debug!("FocusGained"),
+        Event::FocusLost => log::debug!("FocusLost"),
+        Event::Key(event) => {
+            if vim.is_some_and(|vim| vim.handle_key(journal, event)) {
+                return Ok(true);
+            }
+
+            if event.modifiers.contains(KeyModifiers::ALT) {
+                match event.code {
+                    KeyCode::Char('z') => journal.time_travel(Travel::States(-1)),
+                    KeyCode::Char('y') => journal.time_travel(Travel::States(1)),
+                    KeyCode::Char('c') => {
+                        if let Some(name) = prompt(journal, input, "Copy to register: ")? {
+                            journal.copy(Some(&name));
+                        }
+                    },
+                    KeyCode::Char('x') => {
+                        if let Some(name) = prompt(journal, input, "Cut to register: ")? {
+                            journal.cut(Some(&name));
+                        }
+                    },
+                    KeyCode::Char('v') => {
+                        if let Some(name) = prompt(journal, input, "Paste register: ")? {
+                            journal.paste(Some(&name));
+                        }
+                    },
+                    _ => {}
+                }
+                return Ok(true);
+            }
+
+            //Moving with Shift held down selects, any other move ends the selection
+            if is_motion(event.code) {
+                journal.set_selecting(event.modifiers.contains(KeyModifiers::SHIFT));
+            }
+
+            if event.modifiers.contains(KeyModifiers::CONTROL) {
+                match event.code {
+                    KeyCode::Char('q') => {
+                        if journal.is_modified() {
+                            journal.set_status(String::from("Quit without saving? (y/n)"));
+                            let quit = confirm(input)?;
+                            journal.set_status(String::new());
+                            if !quit {
+                                return Ok(true);
+                            }
+                        }
+                        journal.discard_swap()?;
+                        return Ok(false);
+                    },
+                    KeyCode::Char('x') => journal.cut(None),
+                    KeyCode::Char('c') => journal.copy(None),
+                    KeyCode::Char('v') => journal.paste(None),
+                    KeyCode::Char('z') => journal.undo(),
+                    KeyCode::Char('y') => journal.redo(),
+                    KeyCode::Char('t') => {
+                        if let Some(spec) = prompt(journal, input, "Travel to (-5m, +30s, -3, 12): ")? {
+                            match history::parse_travel(&spec) {
+                                Some(travel) => journal.time_travel(travel),
+                                None => journal.set_status(format!("Invalid travel: {}", spec))
+                            }
+                        }
+                    },
+                    KeyCode::Char('b') => journal.list_branches(),
+                    KeyCode::Char('k') => {
+                        if let Some(name) = prompt(journal, input, "Set mark: ")? {
+                            journal.set_bookmark(&name);
+                        }
+                    },
+                    KeyCode::Char('g') => {
+                        if let Some(name) = prompt(journal, input, "Jump to mark: ")? {
+                            journal.jump_to_bookmark(&name);
+                        }
+                    },
+                    KeyCode::Left => journal.move_word_left(),
+                    KeyCode::Right => journal.move_word_right(),
+                    //Most terminals send Ctrl-Backspace as Ctrl-H
+                    KeyCode::Backspace | KeyCode::Char('h') => journal.delete_word_back(),
+                    KeyCode::Delete => journal.delete_word_forward(),
+                    KeyCode::Home => journal.move_to_start(),
+                    KeyCode::End => journal.move_to_end(),
+                    KeyCode::Char('s') => {
+                        if let Err(err) = journal.save() {
+                            log::warn!("Save failed: {}", err);
+                            journal.set_status(format!("Save failed: {}", err));
+                        }
+                    },
+                    _ => {}
+                }
+                return Ok(true);
+            }
+
+            match event.code {
+                KeyCode::Left       => journal.move_cursor(Direction::LEFT, 1),
+                KeyCode::Down       => journal.move_cursor(Direction::DOWN, 1),
+                KeyCode::Up         => journal.move_cursor(Direction::UP, 1),
+                KeyCode::Right      => journal.move_cursor(Direction::RIGHT, 1),
+                KeyCode::Char(ch)   => journal.insert_ch(ch),
+                KeyCode::Enter      => journal.insert_ch('\n'),
+                KeyCode::Tab        => journal.insert_ch('\t'),
+                KeyCode::Backspace  => journal.delete_ch(),
+                KeyCode::Delete     => journal.delete_next_ch(),
+                KeyCode::Esc        => journal.set_selecting(false),
+                KeyCode::Home       => journal.move_home(),
+                KeyCode::End        => journal.move_end(),
+                KeyCode::PageUp     => journal.page_up(),
+                KeyCode::PageDown   => journal.page_down(),
+                _ => {}
+            }
+        },
+        Event::Paste(text) => journal.paste_text(&text),
+        Event::Mouse(event) => match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                journal.click(event.column, event.row, event.modifiers.contains(KeyModifiers::SHIFT));
+            },
+            MouseEventKind::Drag(MouseButton::Left) => journal.drag_to(event.column, event.row),
+            MouseEventKind::ScrollUp => journal.scroll(-SCROLL_ROWS),
+            MouseEventKind::ScrollDown => journal.scroll(SCROLL_ROWS),
+            _ => {}
+        },
+        Event::Resize(columns, rows) => {
+            journal.resize_redraw(ScreenDimensions {
+                row: 0,
+                column: 0,
+                max_rows: rows,
+                max_cols: columns
+            });
+        },
+    }
+
+    Ok(true)
+}
+
+//Rows moved by a turn of the mouse wheel
+const SCROLL_ROWS: isize = 3;
+
+fn is_motion(code: KeyCode) -> bool {
+    matches!(
+        code,
+        KeyCode::Left
+            | KeyCode::Right
+            | KeyCode::Up
+            | KeyCode::Down
+            | KeyCode::Home
+            | KeyCode::End
+            | KeyCode::PageUp
+            | KeyCode::PageDown
+    )
+}
+
+//Blocks until the user answers a yes/no question
+fn confirm(input: &mut dyn Input) -> std::io::Result<bool> {
+    loop {
+        if let Event::Key(event) = input.read()? {
+            match event.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => return Ok(true),
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+}
+
+//Reads a line of input in the status bar, `None` when cancelled with Esc
+fn prompt(journal: &mut Editor, input: &mut dyn Input, label: &str) -> std::io::Result<Option<String>> {
+    let mut answer = String::new();
+    loop {
+        journal.set_status(format!("{}{}", label, answer));
+        if let Event::Key(event) = input.read()? {
+            match event.code {
+                KeyCode::Enter => {
+                    journal.set_status(String::new());
+                    return Ok(Some(answer));
+                },
+                KeyCode::Esc => {
+                    journal.set_status(String::new());
+                    return Ok(None);
+                },
+                KeyCode::Backspace => {
+                    answer.pop();
+                },
+                KeyCode::Char(ch) => answer.push(ch),
+                _ => {}
+            }
+        }
+    }
+}
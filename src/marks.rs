@@ -0,0 +1,89 @@
+use std::ops::Range;
+
+//Which way a mark goes when text is inserted exactly at its position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gravity {
+    //Stays in front of the inserted text
+    Left,
+    //Ends up after the inserted text, like a cursor
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MarkId(usize);
+
+struct Mark {
+    pos: usize,
+    gravity: Gravity,
+}
+
+//Byte positions that follow the text around as it is edited. Slots of removed marks are
+//reused, so a `MarkId` must not be used after `remove`.
+pub struct Marks {
+    marks: Vec<Option<Mark>>,
+    free: Vec<usize>,
+}
+
+impl Marks {
+    pub fn new() -> Self {
+        Marks {
+            marks: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub fn add(self: &mut Self, pos: usize, gravity: Gravity) -> MarkId {
+        let mark = Some(Mark { pos, gravity });
+        match self.free.pop() {
+            Some(slot) => {
+                self.marks[slot] = mark;
+                MarkId(slot)
+            }
+            None => {
+                self.marks.push(mark);
+                MarkId(self.marks.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove(self: &mut Self, id: MarkId) {
+        if let Some(slot) = self.marks.get_mut(id.0) {
+            if slot.take().is_some() {
+                self.free.push(id.0);
+            }
+        }
+    }
+
+    pub fn get(self: &Self, id: MarkId) -> Option<usize> {
+        match self.marks.get(id.0) {
+            Some(Some(mark)) => Some(mark.pos),
+            _ => None,
+        }
+    }
+
+    pub fn set(self: &mut Self, id: MarkId, pos: usize) {
+        if let Some(Some(mark)) = self.marks.get_mut(id.0) {
+            mark.pos = pos;
+        }
+    }
+
+    //`len` bytes were inserted at `pos`
+    pub fn insert(self: &mut Self, pos: usize, len: usize) {
+        for mark in self.marks.iter_mut().flatten() {
+            if mark.pos > pos || (mark.pos == pos && mark.gravity == Gravity::Right) {
+                mark.pos += len;
+            }
+        }
+    }
+
+    //The bytes in `range` were deleted, marks inside it collapse to its start
+    pub fn delete(self: &mut Self, range: Range<usize>) {
+        for mark in self.marks.iter_mut().flatten() {
+            if mark.pos >= range.end {
+                mark.pos -= range.end - range.start;
+            } else if mark.pos > range.start {
+                mark.pos = range.start;
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::cmp;
+use std::ops::Range;
+
+use crate::storage::{check_pos, count_newlines, utf8_len, BufferError, CharsIter, TextStorage};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Source {
+    Original,
+    Added,
+}
+
+//A run of bytes taken from one of the two buffers
+#[derive(Clone, Copy)]
+struct Piece {
+    source: Source,
+    start: usize,
+    len: usize,
+    newlines: usize,
+}
+
+//The loaded text is never modified, inserted text is appended to a second buffer and
+//the document is described by a list of pieces pointing into either of them.
+pub struct PieceTable {
+    original: String,
+    added: String,
+    pieces: Vec<Piece>,
+    len: usize,
+    newlines: usize,
+}
+
+impl PieceTable {
+    fn piece_text(self: &Self, piece: &Piece) -> &str {
+        let buffer = match piece.source {
+            Source::Original => &self.original,
+            Source::Added => &self.added,
+        };
+
+        &buffer[piece.start..piece.start + piece.len]
+    }
+
+    //Piece containing byte `pos` and the offset into it, `pos == len` gives one past the last piece
+    fn locate(self: &Self, pos: usize) -> (usize, usize) {
+        let mut start = 0;
+        for (i, piece) in self.pieces.iter().enumerate() {
+            if pos < start + piece.len {
+                return (i, pos - start);
+            }
+            start += piece.len;
+        }
+
+        (self.pieces.len(), 0)
+    }
+
+    //Makes sure a piece starts at `pos` and returns its index
+    fn split_at(self: &mut Self, pos: usize) -> usize {
+        let (i, offset) = self.locate(pos);
+        if offset == 0 {
+            return i;
+        }
+
+        let piece = self.pieces[i];
+        let newlines = count_newlines(&self.piece_text(&piece).as_bytes()[..offset]);
+        self.pieces[i].len = offset;
+        self.pieces[i].newlines = newlines;
+        self.pieces.insert(
+            i + 1,
+            Piece {
+                source: piece.source,
+                start: piece.start + offset,
+                len: piece.len - offset,
+                newlines: piece.newlines - newlines,
+            },
+        );
+
+        i + 1
+    }
+}
+
+impl From<&str> for PieceTable {
+    fn from(text: &str) -> Self {
+        let mut pieces = Vec::new();
+        if !text.is_empty() {
+            pieces.push(Piece {
+                source: Source::Original,
+                start: 0,
+                len: text.len(),
+                newlines: count_newlines(text.as_bytes()),
+            });
+        }
+
+        PieceTable {
+            original: String::from(text),
+            added: String::new(),
+            pieces,
+            len: text.len(),
+            newlines: count_newlines(text.as_bytes()),
+        }
+    }
+}
+
+impl TextStorage for PieceTable {
+    fn len(self: &Self) -> usize {
+        self.len
+    }
+
+    fn get(self: &Self, pos: usize) -> Result<char, BufferError> {
+        if pos >= self.len {
+            return Err(BufferError::OutOfRange { pos, len: self.len });
+        }
+
+        let (i, offset) = self.locate(pos);
+        let text = self.piece_text(&self.pieces[i]);
+        if !text.is_char_boundary(offset) {
+            return Err(BufferError::InvalidCharBoundary(pos));
+        }
+
+        Ok(text[offset..].chars().next().unwrap())
+    }
+
+    fn is_char_boundary(self: &Self, pos: usize) -> bool {
+        if pos >= self.len {
+            return pos == self.len;
+        }
+
+        let (i, offset) = self.locate(pos);
+        utf8_len(self.piece_text(&self.pieces[i]).as_bytes()[offset]).is_some()
+    }
+
+    fn try_insert_str(self: &mut Self, pos: usize, text: &str) -> Result<(), BufferError> {
+        check_pos(self, pos)?;
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let added_start = self.added.len();
+        let newlines = count_newlines(text.as_bytes());
+        self.added.push_str(text);
+        self.len += text.len();
+        self.newlines += newlines;
+
+        //Typing appends to the piece that was added last, no need for a new one each time
+        let i = self.split_at(pos);
+        if i > 0 {
+            let prev = &mut self.pieces[i - 1];
+            if prev.source == Source::Added && prev.start + prev.len == added_start {
+                prev.len += text.len();
+                prev.newlines += newlines;
+                return Ok(());
+            }
+        }
+
+        self.pieces.insert(
+            i,
+            Piece {
+                source: Source::Added,
+                start: added_start,
+                len: text.len(),
+                newlines,
+            },
+        );
+        Ok(())
+    }
+
+    fn try_delete_range(self: &mut Self, range: Range<usize>) -> Result<(), BufferError> {
+        check_pos(self, range.start)?;
+        check_pos(self, range.end)?;
+        if range.start >= range.end {
+            return Ok(());
+        }
+
+        let first = self.split_at(range.start);
+        let last = self.split_at(range.end);
+        let newlines = self.pieces.drain(first..last).map(|piece| piece.newlines).sum::<usize>();
+        self.len -= range.end - range.start;
+        self.newlines -= newlines;
+        Ok(())
+    }
+
+    fn chars_in(self: &Self, range: Range<usize>) -> CharsIter<'_> {
+        let mut slices = Vec::new();
+        let mut start = 0;
+        for piece in &self.pieces {
+            let end = start + piece.len;
+            if end > range.start && start < range.end {
+                let text = self.piece_text(piece);
+                let from = range.start.saturating_sub(start);
+                let to = cmp::min(range.end, end) - start;
+                slices.push(&text[from..to]);
+            }
+            start = end;
+        }
+
+        Box::new(slices.into_iter().flat_map(|slice| slice.chars()))
+    }
+
+    fn line_count(self: &Self) -> usize {
+        self.newlines + 1
+    }
+
+    //Pieces know how many newlines they hold, only the one with the line start is scanned
+    fn line_to_byte(self: &Self, line: usize) -> usize {
+        if line == 0 {
+            return 0;
+        }
+
+        let (mut start, mut lines) = (0, 0);
+        for piece in &self.pieces {
+            if lines + piece.newlines >= line {
+                let text = self.piece_text(piece);
+                let (i, _) = text.match_indices('\n').nth(line - lines - 1).unwrap();
+                return start + i + 1;
+            }
+            start += piece.len;
+            lines += piece.newlines;
+        }
+
+        self.len
+    }
+
+    fn byte_to_line(self: &Self, pos: usize) -> usize {
+        let (mut start, mut lines) = (0, 0);
+        for piece in &self.pieces {
+            if pos < start + piece.len {
+                let text = self.piece_text(piece);
+                return lines + count_newlines(&text.as_bytes()[..pos - start]);
+            }
+            start += piece.len;
+            lines += piece.newlines;
+        }
+
+        lines
+    }
+}
@@ -0,0 +1,309 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent,
+    MouseEventKind,
+};
+
+use crate::history::{escape, unescape};
+use crate::input::Input;
+
+const RECORDING_MAGIC: &str = "text-editor recording 1";
+
+//Passes events on from another input, writing each one down with the time it arrived.
+//
+//The file starts with the terminal size, then one event per line:
+//`<ms since start> key <modifiers> <press|repeat|release> <code>`,
+//`<ms> mouse <kind> <column> <row> <modifiers>`, `<ms> resize <cols> <rows>`,
+//`<ms> paste <text>` or `<ms> focus-gained|focus-lost`.
+pub struct Recorder {
+    input: Box<dyn Input>,
+    out: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, input: Box<dyn Input>, size: (u16, u16)) -> std::io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "{}\nsize {} {}\n", RECORDING_MAGIC, size.0, size.1)?;
+        out.flush()?;
+
+        Ok(Recorder {
+            input,
+            out,
+            start: Instant::now(),
+        })
+    }
+}
+
+impl Input for Recorder {
+    fn poll(self: &mut Self, timeout: Duration) -> std::io::Result<bool> {
+        self.input.poll(timeout)
+    }
+
+    fn finished(self: &Self) -> bool {
+        self.input.finished()
+    }
+
+    fn read(self: &mut Self) -> std::io::Result<Event> {
+        let event = self.input.read()?;
+        if let Some(line) = event_to_text(&event) {
+            //Flushed right away, the recording matters most when the editor crashes
+            writeln!(self.out, "{} {}", self.start.elapsed().as_millis(), line)?;
+            self.out.flush()?;
+        }
+
+        Ok(event)
+    }
+}
+
+//Feeds a recording back in, each event once as much time has passed as when it was recorded.
+//`fast` skips the waiting, which is quicker but can change anything that depends on timing.
+pub struct Replay {
+    events: Vec<(Duration, Event)>,
+    next: usize,
+    start: Instant,
+    fast: bool,
+    pub size: (u16, u16),
+}
+
+impl Replay {
+    pub fn open(path: &Path, fast: bool) -> std::io::Result<Self> {
+        let invalid =
+            |line: &str| Error::new(ErrorKind::InvalidData, format!("Bad recording line: {}", line));
+
+        let data = fs::read_to_string(path)?;
+        let mut lines = data.lines();
+        if lines.next() != Some(RECORDING_MAGIC) {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a recording"));
+        }
+
+        let size_line = lines.next().unwrap_or_default();
+        let size = size_line
+            .strip_prefix("size ")
+            .and_then(|size| size.split_once(' '))
+            .and_then(|(cols, rows)| Some((cols.parse().ok()?, rows.parse().ok()?)))
+            .ok_or_else(|| invalid(size_line))?;
+
+        let mut events = Vec::new();
+        for line in lines {
+            let (time, event) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+            let time = time.parse::<u64>().map_err(|_| invalid(line))?;
+            let event = text_to_event(event).ok_or_else(|| invalid(line))?;
+            events.push((Duration::from_millis(time), event));
+        }
+
+        Ok(Replay {
+            events,
+            next: 0,
+            start: Instant::now(),
+            fast,
+            size,
+        })
+    }
+
+    //How long until the next event is due
+    fn wait_time(self: &Self) -> Duration {
+        match self.events.get(self.next) {
+            Some((time, _)) if !self.fast => time.saturating_sub(self.start.elapsed()),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+impl Input for Replay {
+    fn finished(self: &Self) -> bool {
+        self.next >= self.events.len()
+    }
+
+    fn poll(self: &mut Self, timeout: Duration) -> std::io::Result<bool> {
+        if self.finished() {
+            return Ok(false);
+        }
+
+        let wait = self.wait_time();
+        thread::sleep(wait.min(timeout));
+        Ok(wait <= timeout)
+    }
+
+    fn read(self: &mut Self) -> std::io::Result<Event> {
+        if self.finished() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "end of recording"));
+        }
+
+        thread::sleep(self.wait_time());
+        self.next += 1;
+        Ok(self.events[self.next - 1].1.clone())
+    }
+}
+
+fn event_to_text(event: &Event) -> Option<String> {
+    let text = match event {
+        Event::FocusGained => String::from("focus-gained"),
+        Event::FocusLost => String::from("focus-lost"),
+        Event::Key(key) => {
+            let kind = match key.kind {
+                KeyEventKind::Press => "press",
+                KeyEventKind::Repeat => "repeat",
+                KeyEventKind::Release => "release",
+            };
+            format!("key {} {} {}", key.modifiers.bits(), kind, key_code_to_text(key.code)?)
+        }
+        Event::Mouse(mouse) => {
+            let kind = match mouse.kind {
+                MouseEventKind::Down(button) => format!("down-{}", button_to_text(button)),
+                MouseEventKind::Up(button) => format!("up-{}", button_to_text(button)),
+                MouseEventKind::Drag(button) => format!("drag-{}", button_to_text(button)),
+                MouseEventKind::Moved => String::from("moved"),
+                MouseEventKind::ScrollDown => String::from("scroll-down"),
+                MouseEventKind::ScrollUp => String::from("scroll-up"),
+                MouseEventKind::ScrollLeft => String::from("scroll-left"),
+                MouseEventKind::ScrollRight => String::from("scroll-right"),
+            };
+            format!("mouse {} {} {} {}", kind, mouse.column, mouse.row, mouse.modifiers.bits())
+        }
+        Event::Paste(text) => format!("paste {}", escape(text)),
+        Event::Resize(columns, rows) => format!("resize {} {}", columns, rows),
+    };
+
+    Some(text)
+}
+
+fn text_to_event(text: &str) -> Option<Event> {
+    let (kind, rest) = text.split_once(' ').unwrap_or((text, ""));
+    let event = match kind {
+        "focus-gained" => Event::FocusGained,
+        "focus-lost" => Event::FocusLost,
+        "key" => {
+            let mut parts = rest.splitn(3, ' ');
+            let modifiers = KeyModifiers::from_bits_truncate(parts.next()?.parse().ok()?);
+            let kind = match parts.next()? {
+                "press" => KeyEventKind::Press,
+                "repeat" => KeyEventKind::Repeat,
+                "release" => KeyEventKind::Release,
+                _ => return None,
+            };
+            let code = text_to_key_code(parts.next()?)?;
+            Event::Key(KeyEvent::new_with_kind(code, modifiers, kind))
+        }
+        "mouse" => {
+            let mut parts = rest.split(' ');
+            let kind = match parts.next()? {
+                "moved" => MouseEventKind::Moved,
+                "scroll-down" => MouseEventKind::ScrollDown,
+                "scroll-up" => MouseEventKind::ScrollUp,
+                "scroll-left" => MouseEventKind::ScrollLeft,
+                "scroll-right" => MouseEventKind::ScrollRight,
+                kind => {
+                    let (action, button) = kind.split_once('-')?;
+                    let button = text_to_button(button)?;
+                    match action {
+                        "down" => MouseEventKind::Down(button),
+                        "up" => MouseEventKind::Up(button),
+                        "drag" => MouseEventKind::Drag(button),
+                        _ => return None,
+                    }
+                }
+            };
+
+            Event::Mouse(MouseEvent {
+                kind,
+                column: parts.next()?.parse().ok()?,
+                row: parts.next()?.parse().ok()?,
+                modifiers: KeyModifiers::from_bits_truncate(parts.next()?.parse().ok()?),
+            })
+        }
+        "paste" => Event::Paste(unescape(rest)?),
+        "resize" => {
+            let (columns, rows) = rest.split_once(' ')?;
+            Event::Resize(columns.parse().ok()?, rows.parse().ok()?)
+        }
+        _ => return None,
+    };
+
+    Some(event)
+}
+
+//Media and modifier keys only show up with keyboard enhancements, which are never turned on
+fn key_code_to_text(code: KeyCode) -> Option<String> {
+    let text = match code {
+        KeyCode::Char(ch) => format!("char {}", escape(&ch.to_string())),
+        KeyCode::F(n) => format!("f {}", n),
+        KeyCode::Backspace => String::from("backspace"),
+        KeyCode::Enter => String::from("enter"),
+        KeyCode::Left => String::from("left"),
+        KeyCode::Right => String::from("right"),
+        KeyCode::Up => String::from("up"),
+        KeyCode::Down => String::from("down"),
+        KeyCode::Home => String::from("home"),
+        KeyCode::End => String::from("end"),
+        KeyCode::PageUp => String::from("page-up"),
+        KeyCode::PageDown => String::from("page-down"),
+        KeyCode::Tab => String::from("tab"),
+        KeyCode::BackTab => String::from("back-tab"),
+        KeyCode::Delete => String::from("delete"),
+        KeyCode::Insert => String::from("insert"),
+        KeyCode::Null => String::from("null"),
+        KeyCode::Esc => String::from("esc"),
+        _ => return None,
+    };
+
+    Some(text)
+}
+
+fn text_to_key_code(text: &str) -> Option<KeyCode> {
+    if let Some(ch) = text.strip_prefix("char ") {
+        let ch = unescape(ch)?;
+        let mut chars = ch.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(ch), None) => Some(KeyCode::Char(ch)),
+            _ => None,
+        };
+    }
+    if let Some(n) = text.strip_prefix("f ") {
+        return Some(KeyCode::F(n.parse().ok()?));
+    }
+
+    let code = match text {
+        "backspace" => KeyCode::Backspace,
+        "enter" => KeyCode::Enter,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "page-up" => KeyCode::PageUp,
+        "page-down" => KeyCode::PageDown,
+        "tab" => KeyCode::Tab,
+        "back-tab" => KeyCode::BackTab,
+        "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "null" => KeyCode::Null,
+        "esc" => KeyCode::Esc,
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+fn button_to_text(button: MouseButton) -> &'static str {
+    match button {
+        MouseButton::Left => "left",
+        MouseButton::Right => "right",
+        MouseButton::Middle => "middle",
+    }
+}
+
+fn text_to_button(text: &str) -> Option<MouseButton> {
+    match text {
+        "left" => Some(MouseButton::Left),
+        "right" => Some(MouseButton::Right),
+        "middle" => Some(MouseButton::Middle),
+        _ => None,
+    }
+}
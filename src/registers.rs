@@ -0,0 +1,52 @@
+use std::collections::{HashMap, VecDeque};
+
+//How many cuts and copies are remembered
+const KILL_RING_SIZE: usize = 10;
+
+//Text that was cut or copied. Every cut and copy goes onto the kill ring, reachable as the
+//registers `0` (the latest) to `9`. Any other name is a register that keeps its text until
+//something else is copied into it.
+pub struct Registers {
+    ring: VecDeque<String>,
+    named: HashMap<String, String>,
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Registers {
+            ring: VecDeque::new(),
+            named: HashMap::new(),
+        }
+    }
+
+    //Remembers `text` on the kill ring and in the register `name`, if one is given
+    pub fn store(self: &mut Self, name: Option<&str>, text: &str) {
+        if let Some(name) = name.filter(|name| ring_index(name).is_none()) {
+            self.named.insert(String::from(name), String::from(text));
+        }
+
+        self.ring.push_front(String::from(text));
+        self.ring.truncate(KILL_RING_SIZE);
+    }
+
+    //Text of the register `name`, or of the latest cut or copy
+    pub fn get(self: &Self, name: Option<&str>) -> Option<&str> {
+        let text = match name {
+            None => self.ring.front(),
+            Some(name) => match ring_index(name) {
+                Some(index) => self.ring.get(index),
+                None => self.named.get(name),
+            },
+        };
+
+        text.map(|text| text.as_str())
+    }
+}
+
+fn ring_index(name: &str) -> Option<usize> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) => ch.to_digit(10).map(|digit| digit as usize),
+        _ => None,
+    }
+}
@@ -0,0 +1,324 @@
+use std::cmp;
+use std::ops::Range;
+
+use crate::storage::{check_pos, count_newlines, utf8_len, BufferError, CharsIter, TextStorage};
+
+//Leaves are split once they grow past this many bytes
+const MAX_LEAF: usize = 1024;
+
+enum Node {
+    Leaf(String),
+    Branch {
+        left: Box<Node>,
+        right: Box<Node>,
+        len: usize,
+        //Newlines in the subtree, so line lookups only walk one path down
+        newlines: usize,
+        depth: usize,
+    },
+}
+
+impl Node {
+    fn branch(left: Node, right: Node) -> Node {
+        Node::Branch {
+            len: left.len() + right.len(),
+            newlines: left.newlines() + right.newlines(),
+            depth: 1 + cmp::max(left.depth(), right.depth()),
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    //Balanced tree over `leaves`, in order
+    fn build(leaves: &mut Vec<String>) -> Node {
+        match leaves.len() {
+            0 => Node::Leaf(String::new()),
+            1 => Node::Leaf(leaves.pop().unwrap()),
+            len => {
+                let mut right = leaves.split_off(len / 2);
+                let left = Node::build(leaves);
+                let right = Node::build(&mut right);
+                Node::branch(left, right)
+            }
+        }
+    }
+
+    fn len(self: &Self) -> usize {
+        match self {
+            Node::Leaf(text) => text.len(),
+            Node::Branch { len, .. } => *len,
+        }
+    }
+
+    fn newlines(self: &Self) -> usize {
+        match self {
+            Node::Leaf(text) => count_newlines(text.as_bytes()),
+            Node::Branch { newlines, .. } => *newlines,
+        }
+    }
+
+    //Offset right after the `n`th newline, counting from 1, of which there have to be enough
+    fn newline_end(self: &Self, n: usize) -> usize {
+        match self {
+            Node::Leaf(text) => text.match_indices('\n').nth(n - 1).map_or(text.len(), |(i, _)| i + 1),
+            Node::Branch { left, right, .. } => {
+                if n <= left.newlines() {
+                    left.newline_end(n)
+                } else {
+                    left.len() + right.newline_end(n - left.newlines())
+                }
+            }
+        }
+    }
+
+    //Newlines in the first `pos` bytes
+    fn newlines_before(self: &Self, pos: usize) -> usize {
+        match self {
+            Node::Leaf(text) => count_newlines(&text.as_bytes()[..pos]),
+            Node::Branch { left, right, .. } => {
+                if pos <= left.len() {
+                    left.newlines_before(pos)
+                } else {
+                    left.newlines() + right.newlines_before(pos - left.len())
+                }
+            }
+        }
+    }
+
+    fn depth(self: &Self) -> usize {
+        match self {
+            Node::Leaf(_) => 0,
+            Node::Branch { depth, .. } => *depth,
+        }
+    }
+
+    //Leaf holding byte `pos` and the offset into it
+    fn leaf_at(self: &Self, pos: usize) -> (&str, usize) {
+        match self {
+            Node::Leaf(text) => (text, pos),
+            Node::Branch { left, right, .. } => {
+                if pos < left.len() {
+                    left.leaf_at(pos)
+                } else {
+                    right.leaf_at(pos - left.len())
+                }
+            }
+        }
+    }
+
+    fn insert(self: &mut Self, pos: usize, text: &str) {
+        match self {
+            Node::Leaf(leaf) => {
+                leaf.insert_str(pos, text);
+                if leaf.len() > MAX_LEAF {
+                    let leaf = std::mem::take(leaf);
+                    *self = Node::build(&mut split_leaves(&leaf, MAX_LEAF / 2));
+                }
+            }
+            Node::Branch {
+                left,
+                right,
+                len,
+                newlines,
+                depth,
+            } => {
+                if pos <= left.len() {
+                    left.insert(pos, text);
+                } else {
+                    right.insert(pos - left.len(), text);
+                }
+                *len += text.len();
+                *newlines = left.newlines() + right.newlines();
+                *depth = 1 + cmp::max(left.depth(), right.depth());
+            }
+        }
+    }
+
+    fn delete(self: &mut Self, start: usize, end: usize) {
+        match self {
+            Node::Leaf(leaf) => leaf.replace_range(start..end, ""),
+            Node::Branch {
+                left,
+                right,
+                len,
+                newlines,
+                depth,
+            } => {
+                let left_len = left.len();
+                if start < left_len {
+                    left.delete(start, cmp::min(end, left_len));
+                }
+                if end > left_len {
+                    right.delete(start.saturating_sub(left_len), end - left_len);
+                }
+
+                *len -= end - start;
+                *newlines = left.newlines() + right.newlines();
+                *depth = 1 + cmp::max(left.depth(), right.depth());
+
+                //Collapse branches that lost one side
+                if left.len() == 0 {
+                    *self = std::mem::replace(right.as_mut(), Node::Leaf(String::new()));
+                } else if right.len() == 0 {
+                    *self = std::mem::replace(left.as_mut(), Node::Leaf(String::new()));
+                }
+            }
+        }
+    }
+
+    fn slices<'a>(self: &'a Self, start: usize, end: usize, out: &mut Vec<&'a str>) {
+        if start >= end {
+            return;
+        }
+
+        match self {
+            Node::Leaf(leaf) => out.push(&leaf[start..end]),
+            Node::Branch { left, right, .. } => {
+                let left_len = left.len();
+                if start < left_len {
+                    left.slices(start, cmp::min(end, left_len), out);
+                }
+                if end > left_len {
+                    right.slices(start.saturating_sub(left_len), end - left_len, out);
+                }
+            }
+        }
+    }
+
+    fn into_leaves(self: Self, out: &mut Vec<String>) {
+        match self {
+            Node::Leaf(leaf) => {
+                if !leaf.is_empty() {
+                    out.push(leaf);
+                }
+            }
+            Node::Branch { left, right, .. } => {
+                left.into_leaves(out);
+                right.into_leaves(out);
+            }
+        }
+    }
+}
+
+//Cuts `text` into pieces of about `size` bytes without splitting a char
+fn split_leaves(text: &str, size: usize) -> Vec<String> {
+    let mut leaves = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let mut end = cmp::min(start + size, text.len());
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
+
+        leaves.push(String::from(&text[start..end]));
+        start = end;
+    }
+
+    leaves
+}
+
+//Binary tree of text chunks, edits only touch the leaves on the path to the position
+//so they stay cheap no matter how large the text gets.
+pub struct Rope {
+    root: Node,
+}
+
+impl Rope {
+    //Rebuilds a balanced tree once edits in one spot made it lopsided
+    fn rebalance(self: &mut Self) {
+        let leaf_count = self.root.len() / (MAX_LEAF / 2) + 1;
+        let max_depth = 2 * (usize::BITS - leaf_count.leading_zeros()) as usize + 8;
+        if self.root.depth() <= max_depth {
+            return;
+        }
+
+        let root = std::mem::replace(&mut self.root, Node::Leaf(String::new()));
+        let mut leaves = Vec::new();
+        root.into_leaves(&mut leaves);
+        self.root = Node::build(&mut leaves);
+    }
+}
+
+impl From<&str> for Rope {
+    fn from(text: &str) -> Self {
+        Rope {
+            root: Node::build(&mut split_leaves(text, MAX_LEAF)),
+        }
+    }
+}
+
+impl TextStorage for Rope {
+    fn len(self: &Self) -> usize {
+        self.root.len()
+    }
+
+    fn get(self: &Self, pos: usize) -> Result<char, BufferError> {
+        if pos >= self.len() {
+            return Err(BufferError::OutOfRange {
+                pos,
+                len: self.len(),
+            });
+        }
+
+        let (leaf, offset) = self.root.leaf_at(pos);
+        if !leaf.is_char_boundary(offset) {
+            return Err(BufferError::InvalidCharBoundary(pos));
+        }
+
+        Ok(leaf[offset..].chars().next().unwrap())
+    }
+
+    fn is_char_boundary(self: &Self, pos: usize) -> bool {
+        if pos >= self.len() {
+            return pos == self.len();
+        }
+
+        let (leaf, offset) = self.root.leaf_at(pos);
+        utf8_len(leaf.as_bytes()[offset]).is_some()
+    }
+
+    fn try_insert_str(self: &mut Self, pos: usize, text: &str) -> Result<(), BufferError> {
+        check_pos(self, pos)?;
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        self.root.insert(pos, text);
+        self.rebalance();
+        Ok(())
+    }
+
+    fn try_delete_range(self: &mut Self, range: Range<usize>) -> Result<(), BufferError> {
+        check_pos(self, range.start)?;
+        check_pos(self, range.end)?;
+        if range.start >= range.end {
+            return Ok(());
+        }
+
+        self.root.delete(range.start, range.end);
+        Ok(())
+    }
+
+    fn chars_in(self: &Self, range: Range<usize>) -> CharsIter<'_> {
+        let mut slices = Vec::new();
+        self.root
+            .slices(range.start, cmp::min(range.end, self.len()), &mut slices);
+        Box::new(slices.into_iter().flat_map(|slice| slice.chars()))
+    }
+
+    fn line_count(self: &Self) -> usize {
+        self.root.newlines() + 1
+    }
+
+    fn line_to_byte(self: &Self, line: usize) -> usize {
+        match line {
+            0 => 0,
+            line if line > self.root.newlines() => self.len(),
+            line => self.root.newline_end(line),
+        }
+    }
+
+    fn byte_to_line(self: &Self, pos: usize) -> usize {
+        self.root.newlines_before(cmp::min(pos, self.len()))
+    }
+}
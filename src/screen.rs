@@ -0,0 +1,134 @@
+use crossterm::style::Color;
+use unicode_width::UnicodeWidthChar;
+
+use crate::backend::Backend;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+pub const BLANK: Cell = Cell {
+    ch: ' ',
+    fg: Color::Reset,
+    bg: Color::Reset,
+};
+
+//Put in the cell to the right of a wide char, which covers both of them. `print` never puts
+//control chars in a cell, so this one can only come from there.
+pub const WIDE_TAIL: char = '\0';
+
+//Shown instead of control chars, printing those as they are could move the terminal cursor or
+//start escape sequences
+pub const CONTROL: char = '\u{fffd}';
+
+//Cells taken by `ch` on the terminal, 2 for wide chars such as CJK and most emoji. Everything
+//else gets one cell, including combining marks and other zero width chars, which the terminal
+//may draw on top of the char before them instead.
+pub fn display_width(ch: char) -> u16 {
+    if ch.width() == Some(2) {
+        2
+    } else {
+        1
+    }
+}
+
+//The next frame, drawn cell by cell in memory. `flush` compares it with the frame that was
+//last sent to the backend and only passes on the cells that changed, then places the cursor.
+pub struct Screen {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+    //What the terminal shows right now, empty when that is unknown
+    flushed: Vec<Cell>,
+    cursor: (u16, u16),
+}
+
+impl Screen {
+    pub fn new(width: u16, height: u16) -> Self {
+        Screen {
+            width,
+            height,
+            cells: vec![BLANK; width as usize * height as usize],
+            flushed: Vec::new(),
+            cursor: (0, 0),
+        }
+    }
+
+    //Starts over with a blank frame, the next flush repaints everything
+    pub fn resize(self: &mut Self, width: u16, height: u16) {
+        *self = Screen::new(width, height);
+    }
+
+    //Overwrites `len` cells from `column` on with spaces
+    pub fn fill(self: &mut Self, column: u16, row: u16, len: u16, fg: Color, bg: Color) {
+        for i in 0..len {
+            self.set(column + i, row, Cell { ch: ' ', fg, bg });
+        }
+    }
+
+    //Writes `text` from `column` on, cutting it off at the edge. A wide char that would only
+    //fit halfway is left out.
+    pub fn print(self: &mut Self, column: u16, row: u16, text: &str, fg: Color, bg: Color) {
+        let mut column = column;
+        for ch in text.chars() {
+            let ch = if ch.is_control() { CONTROL } else { ch };
+            if column >= self.width {
+                break;
+            }
+
+            if display_width(ch) == 2 {
+                if column + 1 >= self.width {
+                    self.set(column, row, Cell { ch: ' ', fg, bg });
+                    break;
+                }
+                self.set(column, row, Cell { ch, fg, bg });
+                self.set(column + 1, row, Cell { ch: WIDE_TAIL, fg, bg });
+                column += 2;
+            } else {
+                self.set(column, row, Cell { ch, fg, bg });
+                column += 1;
+            }
+        }
+    }
+
+    pub fn set_cursor(self: &mut Self, column: u16, row: u16) {
+        self.cursor = (column, row);
+    }
+
+    pub fn flush(self: &mut Self, backend: &mut dyn Backend) -> std::io::Result<()> {
+        for (i, cell) in self.cells.iter().enumerate() {
+            if self.flushed.get(i) == Some(cell) {
+                continue;
+            }
+
+            let column = (i % self.width as usize) as u16;
+            let row = (i / self.width as usize) as u16;
+            backend.draw_cell(column, row, *cell)?;
+        }
+
+        backend.set_cursor(self.cursor.0, self.cursor.1)?;
+        backend.flush()?;
+
+        self.flushed.clone_from(&self.cells);
+        Ok(())
+    }
+
+    //Overwriting half of a wide char blanks the other half
+    fn set(self: &mut Self, column: u16, row: u16, cell: Cell) {
+        if column >= self.width || row >= self.height {
+            return;
+        }
+
+        let i = row as usize * self.width as usize + column as usize;
+        if self.cells[i].ch == WIDE_TAIL && cell.ch != WIDE_TAIL {
+            self.cells[i - 1].ch = ' ';
+        }
+        if display_width(self.cells[i].ch) == 2 && column + 1 < self.width {
+            self.cells[i + 1].ch = ' ';
+        }
+        self.cells[i] = cell;
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::gap_buffer::TextGapBuffer;
+use crate::piece_table::PieceTable;
+use crate::rope::Rope;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BufferError {
+    //Position past the end of the text
+    OutOfRange { pos: usize, len: usize },
+    //Position in the middle of a multi-byte char
+    InvalidCharBoundary(usize),
+    //Capacity in bytes that could not be allocated
+    AllocationFailure(usize),
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(self: &Self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferError::OutOfRange { pos, len } => {
+                write!(f, "Position {} out of range for length {}", pos, len)
+            }
+            BufferError::InvalidCharBoundary(pos) => {
+                write!(f, "Position {} is not a char boundary", pos)
+            }
+            BufferError::AllocationFailure(cap) => write!(f, "Could not allocate {} bytes", cap),
+        }
+    }
+}
+
+impl std::error::Error for BufferError {}
+
+impl From<BufferError> for std::io::Error {
+    fn from(err: BufferError) -> Self {
+        std::io::Error::other(err)
+    }
+}
+
+pub type CharsIter<'a> = Box<dyn DoubleEndedIterator<Item = char> + 'a>;
+
+//Text container the editor works on. Positions are byte offsets into UTF-8 text and
+//have to sit on char boundaries, the `try_` methods report anything else as a `BufferError`.
+//
+//Only the first block has to be implemented, the rest falls back to scanning the text
+//and can be overridden by storages that know better.
+pub trait TextStorage {
+    //Length of the text in bytes
+    fn len(self: &Self) -> usize;
+    //Char starting at byte `pos`
+    fn get(self: &Self, pos: usize) -> Result<char, BufferError>;
+    fn is_char_boundary(self: &Self, pos: usize) -> bool;
+    fn try_insert_str(self: &mut Self, pos: usize, text: &str) -> Result<(), BufferError>;
+    fn try_delete_range(self: &mut Self, range: Range<usize>) -> Result<(), BufferError>;
+    //Chars in `range`, call `rev()` to walk them backwards
+    fn chars_in(self: &Self, range: Range<usize>) -> CharsIter<'_>;
+
+    fn insert_str(self: &mut Self, pos: usize, text: &str) {
+        self.try_insert_str(pos, text).unwrap()
+    }
+
+    fn delete_range(self: &mut Self, range: Range<usize>) {
+        self.try_delete_range(range).unwrap()
+    }
+
+    //Chars from byte `pos` to the end
+    fn chars_at(self: &Self, pos: usize) -> CharsIter<'_> {
+        self.chars_in(pos..self.len())
+    }
+
+    //Chars before byte `pos`, nearest first
+    fn chars_rev_at(self: &Self, pos: usize) -> std::iter::Rev<CharsIter<'_>> {
+        self.chars_in(0..pos).rev()
+    }
+
+    fn text_range(self: &Self, range: Range<usize>) -> String {
+        self.chars_in(range).collect()
+    }
+
+    fn text(self: &Self) -> String {
+        self.text_range(0..self.len())
+    }
+
+    //Start of the char after the one at `pos`, or the end of the text
+    fn next_char_boundary(self: &Self, pos: usize) -> usize {
+        match self.get(pos) {
+            Ok(ch) => pos + ch.len_utf8(),
+            Err(_) => self.len(),
+        }
+    }
+
+    //Start of the char before `pos`, or 0
+    fn prev_char_boundary(self: &Self, pos: usize) -> usize {
+        let mut pos = std::cmp::min(pos, self.len());
+        while pos > 0 {
+            pos -= 1;
+            if self.is_char_boundary(pos) {
+                break;
+            }
+        }
+
+        pos
+    }
+
+    //Number of chars in the first `pos` bytes
+    #[allow(dead_code)]
+    fn byte_to_char(self: &Self, pos: usize) -> usize {
+        self.chars_in(0..pos).count()
+    }
+
+    //Byte offset of the `char_index`th char, or the end of the text
+    #[allow(dead_code)]
+    fn char_to_byte(self: &Self, char_index: usize) -> usize {
+        self.chars_at(0)
+            .take(char_index)
+            .map(|ch| ch.len_utf8())
+            .sum()
+    }
+
+    //Lines are looked up by byte offset, not by char index, because byte offsets are what every
+    //position in the editor is. A char based lookup would need a `byte_to_char` scan on top.
+    //All storages override these three with an index instead of the scans below.
+
+    //Number of logical lines, a trailing newline starts one more empty line
+    fn line_count(self: &Self) -> usize {
+        self.chars_at(0).filter(|ch| *ch == '\n').count() + 1
+    }
+
+    //Byte offset where logical line `line` starts, or the end of the text
+    fn line_to_byte(self: &Self, line: usize) -> usize {
+        if line == 0 {
+            return 0;
+        }
+
+        let mut lines = 0;
+        let mut pos = 0;
+        for ch in self.chars_at(0) {
+            pos += ch.len_utf8();
+            if ch == '\n' {
+                lines += 1;
+                if lines == line {
+                    return pos;
+                }
+            }
+        }
+
+        self.len()
+    }
+
+    //Logical line containing byte `pos`
+    fn byte_to_line(self: &Self, pos: usize) -> usize {
+        self.chars_in(0..pos).filter(|ch| *ch == '\n').count()
+    }
+}
+
+//Storage picked with `--storage` on the command line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StorageKind {
+    GapBuffer,
+    Rope,
+    PieceTable,
+}
+
+impl StorageKind {
+    #[allow(dead_code)]
+    pub const ALL: [StorageKind; 3] = [
+        StorageKind::GapBuffer,
+        StorageKind::Rope,
+        StorageKind::PieceTable,
+    ];
+
+    pub fn parse(name: &str) -> Option<StorageKind> {
+        match name {
+            "gap" | "gap-buffer" => Some(StorageKind::GapBuffer),
+            "rope" => Some(StorageKind::Rope),
+            "piece" | "piece-table" => Some(StorageKind::PieceTable),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn name(self: &Self) -> &'static str {
+        match self {
+            StorageKind::GapBuffer => "gap-buffer",
+            StorageKind::Rope => "rope",
+            StorageKind::PieceTable => "piece-table",
+        }
+    }
+
+    pub fn create(self: &Self, text: &str) -> Box<dyn TextStorage> {
+        match self {
+            StorageKind::GapBuffer => Box::new(TextGapBuffer::from(text)),
+            StorageKind::Rope => Box::new(Rope::from(text)),
+            StorageKind::PieceTable => Box::new(PieceTable::from(text)),
+        }
+    }
+}
+
+//Shared bounds check, valid positions are char boundaries from 0 up to and including the length
+pub fn check_pos(storage: &dyn TextStorage, pos: usize) -> Result<(), BufferError> {
+    if pos > storage.len() {
+        return Err(BufferError::OutOfRange {
+            pos,
+            len: storage.len(),
+        });
+    }
+
+    if !storage.is_char_boundary(pos) {
+        return Err(BufferError::InvalidCharBoundary(pos));
+    }
+
+    Ok(())
+}
+
+//Length of the UTF-8 sequence started by `byte`, `None` for continuation bytes
+pub fn utf8_len(byte: u8) -> Option<usize> {
+    match byte {
+        0x00..=0x7f => Some(1),
+        0xc0..=0xdf => Some(2),
+        0xe0..=0xef => Some(3),
+        0xf0..=0xf7 => Some(4),
+        _ => None,
+    }
+}
+
+pub fn count_newlines(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|byte| **byte == b'\n').count()
+}
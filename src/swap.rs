@@ -0,0 +1,84 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::file;
+
+const SWAP_MAGIC: &str = "text-editor swap 1";
+
+//Contents of a swap file left behind by an earlier session
+pub struct SwapInfo {
+    pub pid: u32,
+    pub contents: String,
+}
+
+impl SwapInfo {
+    //A swap file is stale once the process that wrote it is gone. It is never our own, this
+    //session has not written anything yet, so our pid in it was reused from a dead process.
+    pub fn is_stale(self: &Self) -> bool {
+        self.pid == std::process::id() || !process_alive(self.pid)
+    }
+}
+
+//`dir/file.txt` is journaled to `dir/.file.txt.swp`
+pub fn swap_path(path: &Path) -> std::io::Result<PathBuf> {
+    file::sibling_path(path, |name| format!(".{}.swp", name))
+}
+
+//The swap file gets the permissions of `file_path`, it holds the same text
+pub fn write(swap_path: &Path, file_path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut data = String::with_capacity(SWAP_MAGIC.len() + contents.len() + 16);
+    data.push_str(SWAP_MAGIC);
+    data.push('\n');
+    data.push_str(&std::process::id().to_string());
+    data.push('\n');
+    data.push_str(contents);
+
+    file::write_atomic_like(swap_path, data.as_bytes(), false, Some(file_path))
+}
+
+pub fn read(swap_path: &Path) -> std::io::Result<Option<SwapInfo>> {
+    let data = match fs::read_to_string(swap_path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let mut parts = data.splitn(3, '\n');
+    let magic = parts.next();
+    let pid = parts.next().and_then(|pid| pid.parse::<u32>().ok());
+    let contents = parts.next();
+
+    match (magic, pid, contents) {
+        (Some(SWAP_MAGIC), Some(pid), Some(contents)) => Ok(Some(SwapInfo {
+            pid,
+            contents: String::from(contents),
+        })),
+        _ => Err(Error::new(ErrorKind::InvalidData, "read: Not a swap file")),
+    }
+}
+
+pub fn remove(swap_path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(swap_path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+//Zombies count as dead, they will never touch the swap file again
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> bool {
+    match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => match stat.rsplit_once(") ") {
+            Some((_, rest)) => !rest.starts_with('Z') && !rest.starts_with('X'),
+            None => true,
+        },
+        Err(_) => false,
+    }
+}
+
+//Without a cheap way to ask, assume the writer crashed so the user still gets offered a recovery
+#[cfg(not(target_os = "linux"))]
+fn process_alive(_pid: u32) -> bool {
+    false
+}
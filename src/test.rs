@@ -1,6 +1,16 @@
-mod journal;
-mod logger;
+#![allow(
+    dead_code,
+    clippy::needless_arbitrary_self_type,
+    clippy::needless_return,
+    clippy::needless_range_loop,
+    clippy::upper_case_acronyms
+)]
+
 mod gap_buffer;
+mod line_index;
+mod piece_table;
+mod rope;
+mod storage;
 
 use gap_buffer::TextGapBuffer;
 
@@ -12,14 +22,11 @@ fn main () {
     gap_buffer.insert_ch('c');
     gap_buffer.insert_ch('\n');
 
-    let lines = gap_buffer.get_line_map(5).unwrap();
-    for i in 0..lines.len() {
-        println!("index: {}, len: {}", &lines[i].index, &lines[i].len);
+    for i in 0..gap_buffer.len() {
+        println!("index: {}, ch: {:?}", i, gap_buffer.get(i).unwrap());
     }
 
     println!("len: {}", gap_buffer.len());
-    println!("len_with_gap: {}", gap_buffer.len_with_gap());
-    println!("cap: {}", gap_buffer.cap());
 
     //gap_buffer.move_window(9);
     //gap_buffer.delete_ch();
@@ -0,0 +1,1577 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{Level, LevelFilter, Log, Record};
+
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
+use crossterm::style::Color;
+
+use crate::backend::{Backend, CrosstermBackend, MemoryBackend};
+use crate::editor::{Editor, LineInfo, ScreenDimensions, SELECTION};
+use crate::handle_event;
+use crate::input::Input;
+use crate::logger::FileLogger;
+use crate::marks::{Gravity, Marks};
+use crate::recording::{Recorder, Replay};
+use crate::gap_buffer::TextGapBuffer;
+use crate::line_index::LineIndex;
+use crate::line_map::LineMap;
+use crate::piece_table::PieceTable;
+use crate::rope::Rope;
+use crate::screen::{Screen, CONTROL, WIDE_TAIL};
+use crate::storage::{BufferError, StorageKind, TextStorage};
+use crate::swap;
+use crate::undo_file;
+use crate::vim::Vim;
+
+//The text area starts two cells in from the window edges
+const MARGIN: u16 = 2;
+
+//Events fed to the editor in order, prompts take their answers from the same queue
+struct Keys(VecDeque<Event>);
+
+impl Input for Keys {
+    fn poll(self: &mut Self, _timeout: Duration) -> std::io::Result<bool> {
+        Ok(!self.0.is_empty())
+    }
+
+    fn finished(self: &Self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn read(self: &mut Self) -> std::io::Result<Event> {
+        self.0
+            .pop_front()
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "out of keys"))
+    }
+}
+
+struct Session {
+    editor: Editor,
+    screen: MemoryBackend,
+    vim: Option<Vim>,
+}
+
+impl Session {
+    //A 30x12 window, which leaves 26x8 cells for the text
+    fn new() -> Self {
+        Session::with_storage(StorageKind::GapBuffer)
+    }
+
+    fn with_storage(storage: StorageKind) -> Self {
+        let (screen, window_dim) = Session::window();
+        let editor = Editor::new(
+            window_dim,
+            String::from("[Test]"),
+            storage,
+            Box::new(screen.clone()),
+        );
+
+        Session {
+            editor,
+            screen,
+            vim: None,
+        }
+    }
+
+    fn open(path: &Path) -> Self {
+        let (screen, window_dim) = Session::window();
+        let editor = Editor::open(
+            window_dim,
+            String::from("[Test]"),
+            StorageKind::GapBuffer,
+            Box::new(screen.clone()),
+            path,
+        )
+        .unwrap();
+
+        Session {
+            editor,
+            screen,
+            vim: None,
+        }
+    }
+
+    fn window() -> (MemoryBackend, ScreenDimensions) {
+        let window_dim = ScreenDimensions {
+            row: 0,
+            column: 0,
+            max_rows: 12,
+            max_cols: 30,
+        };
+        (MemoryBackend::new(30, 12), window_dim)
+    }
+
+    //Vim keys on, with `text` typed in and the cursor back at the start in normal mode
+    fn with_vim(text: &str) -> Self {
+        let mut session = Session::new();
+        session.vim = Some(Vim::new());
+        session.type_text(&format!("i{}", text));
+        session.press(vec![key(KeyCode::Esc)]);
+        session.type_text("gg");
+        session
+    }
+
+    fn press(self: &mut Self, events: Vec<Event>) {
+        let mut keys = Keys(VecDeque::from(events));
+        while let Some(event) = keys.0.pop_front() {
+            assert!(handle_event(&mut self.editor, self.vim.as_mut(), event, &mut keys).unwrap());
+        }
+    }
+
+    //Runs `events` like `press`, true once the editor asks to quit
+    fn quits(self: &mut Self, events: Vec<Event>) -> bool {
+        let mut keys = Keys(VecDeque::from(events));
+        while let Some(event) = keys.0.pop_front() {
+            if !handle_event(&mut self.editor, self.vim.as_mut(), event, &mut keys).unwrap() {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn type_text(self: &mut Self, text: &str) {
+        self.press(keys(text));
+    }
+
+    //The rows of the text area, without trailing blanks
+    fn rows(self: &Self) -> Vec<String> {
+        (MARGIN..MARGIN + 8)
+            .map(|row| {
+                self.screen
+                    .row_text(row)
+                    .chars()
+                    .skip(MARGIN as usize)
+                    .collect()
+            })
+            .collect()
+    }
+
+    //Cursor position inside the text area
+    fn cursor(self: &Self) -> (u16, u16) {
+        let (column, row) = self.screen.cursor();
+        (column - MARGIN, row - MARGIN)
+    }
+
+    //The highlighted part of each row of the text area
+    fn selected(self: &Self) -> Vec<String> {
+        (MARGIN..MARGIN + 8)
+            .map(|row| {
+                (MARGIN..30 - MARGIN)
+                    .map(|column| self.screen.cell(column, row))
+                    .filter(|cell| cell.bg == SELECTION && cell.ch != WIDE_TAIL)
+                    .map(|cell| cell.ch)
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn title(self: &Self) -> String {
+        self.screen.row_text(0)
+    }
+
+    fn status(self: &Self) -> String {
+        self.screen.row_text(11)
+    }
+}
+
+fn key(code: KeyCode) -> Event {
+    Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+}
+
+fn ctrl(ch: char) -> Event {
+    key_with(KeyCode::Char(ch), KeyModifiers::CONTROL)
+}
+
+fn key_with(code: KeyCode, modifiers: KeyModifiers) -> Event {
+    Event::Key(KeyEvent::new(code, modifiers))
+}
+
+//A mouse event at a cell of the text area
+fn mouse(kind: MouseEventKind, column: u16, row: u16) -> Event {
+    Event::Mouse(MouseEvent {
+        kind,
+        column: column + MARGIN,
+        row: row + MARGIN,
+        modifiers: KeyModifiers::NONE,
+    })
+}
+
+fn click(column: u16, row: u16) -> Event {
+    mouse(MouseEventKind::Down(MouseButton::Left), column, row)
+}
+
+fn keys(text: &str) -> Vec<Event> {
+    text.chars()
+        .map(|ch| match ch {
+            '\n' => key(KeyCode::Enter),
+            '\t' => key(KeyCode::Tab),
+            ch => key(KeyCode::Char(ch)),
+        })
+        .collect()
+}
+
+fn repeat(event: Event, times: usize) -> Vec<Event> {
+    vec![event; times]
+}
+
+#[test]
+fn typing_shows_text_and_moves_cursor() {
+    let mut session = Session::new();
+    session.type_text("hello\nworld");
+
+    assert_eq!(session.rows()[..3], ["hello", "world", ""]);
+    assert_eq!(session.cursor(), (5, 1));
+}
+
+#[test]
+fn long_lines_wrap_at_the_last_space() {
+    let mut session = Session::new();
+    session.type_text("the quick brown fox jumps over the lazy dog");
+
+    assert_eq!(
+        session.rows()[..3],
+        ["the quick brown fox jumps", "over the lazy dog", ""]
+    );
+    assert_eq!(session.cursor(), (17, 1));
+}
+
+#[test]
+fn words_longer_than_the_line_are_cut() {
+    let mut session = Session::new();
+    session.type_text(&"x".repeat(30));
+
+    assert_eq!(session.rows()[..2], ["x".repeat(26), "x".repeat(4)]);
+    assert_eq!(session.cursor(), (4, 1));
+}
+
+#[test]
+fn vertical_moves_keep_the_column_within_shorter_lines() {
+    let mut session = Session::new();
+    session.type_text("long line\nab\nlonger line");
+    assert_eq!(session.cursor(), (11, 2));
+
+    session.press(vec![key(KeyCode::Up)]);
+    assert_eq!(session.cursor(), (2, 1));
+
+    session.press(vec![key(KeyCode::Up)]);
+    assert_eq!(session.cursor(), (9, 0));
+}
+
+#[test]
+fn left_and_right_cross_line_ends() {
+    let mut session = Session::new();
+    session.type_text("ab\ncd");
+
+    session.press(repeat(key(KeyCode::Left), 3));
+    assert_eq!(session.cursor(), (2, 0));
+
+    session.press(vec![key(KeyCode::Right)]);
+    assert_eq!(session.cursor(), (0, 1));
+}
+
+#[test]
+fn backspace_joins_lines() {
+    let mut session = Session::new();
+    session.type_text("ab\ncd");
+    session.press(repeat(key(KeyCode::Left), 2));
+    session.press(vec![key(KeyCode::Backspace)]);
+
+    assert_eq!(session.rows()[..2], ["abcd", ""]);
+    assert_eq!(session.cursor(), (2, 0));
+}
+
+#[test]
+fn delete_removes_the_char_under_the_cursor() {
+    let mut session = Session::new();
+    session.type_text("ab\ncd");
+    session.press(vec![key_with(KeyCode::Home, KeyModifiers::CONTROL), key(KeyCode::Delete)]);
+    assert_eq!(session.rows()[..2], ["b", "cd"]);
+
+    session.press(vec![key(KeyCode::End), key(KeyCode::Delete)]);
+    assert_eq!(session.rows()[..2], ["bcd", ""]);
+    assert_eq!(session.cursor(), (1, 0));
+
+    session.press(repeat(ctrl('z'), 2));
+    assert_eq!(session.rows()[..2], ["ab", "cd"]);
+}
+
+#[test]
+fn home_and_end_stop_at_the_visual_line_first() {
+    let mut session = Session::new();
+    session.type_text("the quick brown fox jumps over the lazy dog");
+    assert_eq!(session.cursor(), (17, 1));
+
+    session.press(vec![key(KeyCode::Home)]);
+    assert_eq!(session.cursor(), (0, 1));
+    session.press(vec![key(KeyCode::Home)]);
+    assert_eq!(session.cursor(), (0, 0));
+
+    session.press(vec![key(KeyCode::End)]);
+    assert_eq!(session.cursor(), (26, 0));
+    session.press(vec![key(KeyCode::End)]);
+    assert_eq!(session.cursor(), (17, 1));
+}
+
+#[test]
+fn pages_scroll_by_a_screenful() {
+    let mut session = Session::new();
+    let lines: Vec<String> = (1..=30).map(|i| i.to_string()).collect();
+    session.type_text(&lines.join("\n"));
+    session.press(vec![key_with(KeyCode::Home, KeyModifiers::CONTROL)]);
+    assert_eq!(session.rows(), lines[..8]);
+
+    session.press(vec![key(KeyCode::Down), key(KeyCode::PageDown)]);
+    assert_eq!(session.rows(), lines[8..16]);
+    assert_eq!(session.cursor(), (0, 1));
+
+    //The last page ends with the last line
+    session.press(repeat(key(KeyCode::PageDown), 2));
+    assert_eq!(session.rows(), lines[22..]);
+    session.press(vec![key(KeyCode::PageDown)]);
+    assert_eq!(session.cursor(), (0, 7));
+
+    session.press(vec![key(KeyCode::PageUp)]);
+    assert_eq!(session.rows(), lines[14..22]);
+    assert_eq!(session.cursor(), (0, 7));
+
+    session.press(vec![key_with(KeyCode::End, KeyModifiers::CONTROL)]);
+    assert_eq!(session.rows(), lines[22..]);
+    assert_eq!(session.cursor(), (2, 7));
+}
+
+#[test]
+fn ctrl_arrows_move_by_words() {
+    let mut session = Session::new();
+    session.type_text("foo.bar(baz)  qux\nnext");
+    session.press(vec![key_with(KeyCode::Home, KeyModifiers::CONTROL)]);
+
+    let ctrl_right = key_with(KeyCode::Right, KeyModifiers::CONTROL);
+    let mut stops = Vec::new();
+    for _ in 0..8 {
+        session.press(vec![ctrl_right.clone()]);
+        stops.push(session.cursor());
+    }
+    assert_eq!(
+        stops,
+        [(3, 0), (4, 0), (7, 0), (8, 0), (11, 0), (12, 0), (17, 0), (0, 1)]
+    );
+
+    let ctrl_left = key_with(KeyCode::Left, KeyModifiers::CONTROL);
+    session.press(vec![ctrl_left.clone()]);
+    assert_eq!(session.cursor(), (17, 0));
+    session.press(vec![ctrl_left]);
+    assert_eq!(session.cursor(), (14, 0));
+}
+
+#[test]
+fn word_chars_can_be_configured() {
+    let mut session = Session::new();
+    session.type_text("naïve_wörd-x");
+    let ctrl_left = key_with(KeyCode::Left, KeyModifiers::CONTROL);
+
+    session.press(vec![ctrl_left.clone()]);
+    assert_eq!(session.cursor(), (11, 0));
+    session.press(vec![ctrl_left.clone()]);
+    assert_eq!(session.cursor(), (10, 0));
+    session.press(vec![ctrl_left.clone()]);
+    assert_eq!(session.cursor(), (0, 0));
+
+    session.editor.set_word_chars("_-");
+    session.press(vec![key_with(KeyCode::End, KeyModifiers::CONTROL), ctrl_left]);
+    assert_eq!(session.cursor(), (0, 0));
+}
+
+#[test]
+fn ctrl_backspace_and_ctrl_delete_remove_words() {
+    let mut session = Session::new();
+    session.type_text("let value = 10;");
+
+    session.press(vec![key_with(KeyCode::Backspace, KeyModifiers::CONTROL)]);
+    assert_eq!(session.rows()[0], "let value = 10");
+    session.press(vec![ctrl('h')]);
+    //Rows come without trailing blanks, the space is still there
+    assert_eq!(session.rows()[0], "let value =");
+    assert_eq!(session.cursor(), (12, 0));
+
+    session.press(vec![
+        key_with(KeyCode::Home, KeyModifiers::CONTROL),
+        key_with(KeyCode::Delete, KeyModifiers::CONTROL),
+    ]);
+    assert_eq!(session.rows()[0], " value =");
+    session.press(vec![key_with(KeyCode::Delete, KeyModifiers::CONTROL)]);
+    assert_eq!(session.rows()[0], " =");
+    assert_eq!(session.cursor(), (0, 0));
+}
+
+#[test]
+fn shift_arrows_select_and_typing_replaces_the_selection() {
+    let mut session = Session::new();
+    session.type_text("hello world");
+    session.press(repeat(key_with(KeyCode::Left, KeyModifiers::SHIFT), 5));
+    assert_eq!(session.selected()[0], "world");
+
+    session.type_text("there");
+    assert_eq!(session.rows()[0], "hello there");
+    assert_eq!(session.selected()[0], "");
+
+    session.press(vec![ctrl('z')]);
+    assert_eq!(session.rows()[0], "hello world");
+}
+
+#[test]
+fn selections_span_lines_and_are_deleted_at_once() {
+    let mut session = Session::new();
+    session.type_text("one two\nthree\nfour");
+    session.press(vec![
+        key_with(KeyCode::Home, KeyModifiers::CONTROL),
+        key_with(KeyCode::Right, KeyModifiers::CONTROL),
+    ]);
+
+    let shift = KeyModifiers::SHIFT;
+    session.press(vec![key_with(KeyCode::Down, shift), key_with(KeyCode::End, shift)]);
+    //The selected line break shows as one more cell
+    assert_eq!(session.selected()[..3], [" two ", "three", ""]);
+
+    session.press(vec![key_with(KeyCode::Right, shift | KeyModifiers::CONTROL)]);
+    assert_eq!(session.selected()[..3], [" two ", "three ", ""]);
+
+    session.press(vec![key(KeyCode::Backspace)]);
+    assert_eq!(session.rows()[..2], ["onefour", ""]);
+    assert_eq!(session.cursor(), (3, 0));
+}
+
+#[test]
+fn moving_without_shift_ends_the_selection() {
+    let mut session = Session::new();
+    session.type_text("abc");
+    session.press(vec![key_with(KeyCode::Home, KeyModifiers::SHIFT)]);
+    assert_eq!(session.selected()[0], "abc");
+
+    session.press(vec![key(KeyCode::Right)]);
+    assert_eq!(session.selected()[0], "");
+
+    session.press(vec![key(KeyCode::Delete)]);
+    assert_eq!(session.rows()[0], "ac");
+}
+
+#[test]
+fn cut_and_paste_move_text_in_one_undo_step() {
+    let mut session = Session::new();
+    session.type_text("hello world");
+    session.press(vec![
+        key_with(KeyCode::Left, KeyModifiers::SHIFT | KeyModifiers::CONTROL),
+        ctrl('x'),
+    ]);
+    assert_eq!(session.rows()[0], "hello");
+
+    session.press(vec![key_with(KeyCode::Home, KeyModifiers::CONTROL), ctrl('v')]);
+    assert_eq!(session.rows()[0], "worldhello");
+    assert_eq!(session.cursor(), (5, 0));
+
+    session.press(vec![ctrl('z')]);
+    assert_eq!(session.rows()[0], "hello");
+}
+
+#[test]
+fn copies_go_to_registers_and_the_kill_ring() {
+    let alt = |ch| key_with(KeyCode::Char(ch), KeyModifiers::ALT);
+    let mut session = Session::new();
+    session.type_text("ab");
+    session.press(vec![key_with(KeyCode::Left, KeyModifiers::SHIFT)]);
+    session.press([vec![alt('c')], keys("r\n")].concat());
+    session.press(vec![key_with(KeyCode::Home, KeyModifiers::SHIFT), ctrl('c')]);
+    assert_eq!(session.status().trim(), "=== Copied 2 bytes");
+
+    //Pasting over the selection replaces it
+    session.press([vec![alt('v')], keys("r\n")].concat());
+    assert_eq!(session.rows()[0], "b");
+    session.press([vec![alt('v')], keys("1\n")].concat());
+    assert_eq!(session.rows()[0], "bb");
+    session.press(vec![ctrl('v')]);
+    assert_eq!(session.rows()[0], "bbab");
+
+    session.press([vec![alt('v')], keys("q\n")].concat());
+    assert_eq!(session.status().trim(), "=== Register 'q' is empty");
+}
+
+#[test]
+fn copies_reach_the_system_clipboard_with_osc52() {
+    let mut session = Session::new();
+    session.type_text("héllo");
+    session.press(vec![key_with(KeyCode::Home, KeyModifiers::SHIFT), ctrl('c')]);
+    assert_eq!(session.screen.clipboard(), None);
+
+    session.editor.set_osc52(true);
+    session.press(vec![ctrl('c')]);
+    assert_eq!(session.screen.clipboard().as_deref(), Some("héllo"));
+
+    let mut out = Vec::new();
+    CrosstermBackend::new(&mut out).set_clipboard("héllo").unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "\x1b]52;c;aMOpbGxv\x07");
+}
+
+#[test]
+fn pastes_go_in_at_once_as_one_undo_step() {
+    let mut session = Session::new();
+    session.type_text("x");
+    session.press(vec![Event::Paste(String::from("fn main() {\r\tx\r\n}"))]);
+    assert_eq!(session.rows()[..3], ["xfn main() {", "    x", "}"]);
+    assert_eq!(session.cursor(), (1, 2));
+
+    session.press(vec![ctrl('z')]);
+    assert_eq!(session.rows()[..2], ["x", ""]);
+}
+
+#[test]
+fn clicks_place_the_cursor_on_the_text() {
+    let mut session = Session::new();
+    session.type_text("\tab\nthe quick brown fox jumps over the lazy dog");
+
+    //Clicks inside a tab snap to its closer edge
+    session.press(vec![click(1, 0)]);
+    assert_eq!(session.cursor(), (0, 0));
+    session.press(vec![click(3, 0)]);
+    assert_eq!(session.cursor(), (4, 0));
+
+    //Past the end of a row and below the text
+    session.press(vec![click(20, 0)]);
+    assert_eq!(session.cursor(), (6, 0));
+    session.press(vec![click(4, 6)]);
+    assert_eq!(session.cursor(), (4, 2));
+
+    session.type_text("_");
+    assert_eq!(session.rows()[2], "over_ the lazy dog");
+}
+
+#[test]
+fn dragging_selects() {
+    let mut session = Session::new();
+    session.type_text("hello world\nagain");
+    session.press(vec![
+        click(2, 0),
+        mouse(MouseEventKind::Drag(MouseButton::Left), 3, 1),
+        mouse(MouseEventKind::Up(MouseButton::Left), 3, 1),
+    ]);
+    assert_eq!(session.selected()[..2], ["llo world ", "aga"]);
+
+    //Shift-click extends the selection
+    session.press(vec![Event::Mouse(MouseEvent {
+        kind: MouseEventKind::Down(MouseButton::Left),
+        column: MARGIN + 5,
+        row: MARGIN,
+        modifiers: KeyModifiers::SHIFT,
+    })]);
+    assert_eq!(session.selected()[..2], ["llo", ""]);
+    assert_eq!(session.cursor(), (5, 0));
+}
+
+#[test]
+fn double_clicks_select_words_and_triple_clicks_lines() {
+    let mut session = Session::new();
+    session.type_text("one two.three\nfour");
+
+    session.press(repeat(click(5, 0), 2));
+    assert_eq!(session.selected()[0], "two");
+
+    session.press(vec![click(5, 0)]);
+    assert_eq!(session.selected()[..2], ["one two.three ", ""]);
+
+    session.type_text("x");
+    assert_eq!(session.rows()[..2], ["xfour", ""]);
+}
+
+#[test]
+fn the_wheel_scrolls_without_moving_the_text_cursor() {
+    let mut session = Session::new();
+    let lines: Vec<String> = (1..=30).map(|i| i.to_string()).collect();
+    session.type_text(&lines.join("\n"));
+    session.press(vec![key_with(KeyCode::Home, KeyModifiers::CONTROL)]);
+
+    session.press(vec![mouse(MouseEventKind::ScrollDown, 0, 0)]);
+    assert_eq!(session.rows(), lines[3..11]);
+    //Pushed along by the top edge
+    assert_eq!(session.cursor(), (0, 0));
+
+    session.press(vec![mouse(MouseEventKind::ScrollUp, 0, 0)]);
+    assert_eq!(session.rows(), lines[..8]);
+    assert_eq!(session.cursor(), (0, 3));
+
+    session.press(repeat(mouse(MouseEventKind::ScrollDown, 0, 0), 20));
+    assert_eq!(session.rows(), lines[22..]);
+}
+
+#[test]
+fn vim_motions_take_counts() {
+    let mut session = Session::with_vim("one two three\nfour five\nsix");
+    assert_eq!(session.cursor(), (0, 0));
+
+    session.type_text("2w");
+    assert_eq!(session.cursor(), (8, 0));
+    session.type_text("e");
+    assert_eq!(session.cursor(), (12, 0));
+    session.type_text("b");
+    assert_eq!(session.cursor(), (8, 0));
+
+    //The column is kept across shorter lines
+    session.type_text("jj");
+    assert_eq!(session.cursor(), (2, 2));
+    session.type_text("k");
+    assert_eq!(session.cursor(), (8, 1));
+
+    session.type_text("0");
+    assert_eq!(session.cursor(), (0, 1));
+    session.type_text("$");
+    assert_eq!(session.cursor(), (8, 1));
+    session.type_text("G");
+    assert_eq!(session.cursor(), (0, 2));
+    session.type_text("2G");
+    assert_eq!(session.cursor(), (0, 1));
+}
+
+#[test]
+fn vim_operators_combine_with_motions_and_counts() {
+    let mut session = Session::with_vim("a b c d e f\nsecond\nthird");
+
+    session.type_text("dw");
+    assert_eq!(session.rows()[0], "b c d e f");
+    session.type_text("2d2w");
+    assert_eq!(session.rows()[0], "f");
+    session.type_text("cwzz");
+    session.press(vec![key(KeyCode::Esc)]);
+    assert_eq!(session.rows()[0], "zz");
+    assert_eq!(session.cursor(), (1, 0));
+
+    session.type_text("jdd");
+    assert_eq!(session.rows()[..3], ["zz", "third", ""]);
+    assert_eq!(session.cursor(), (0, 1));
+
+    //Yanked lines are put below the last line as well
+    session.type_text("ggyyGp");
+    assert_eq!(session.rows()[..3], ["zz", "third", "zz"]);
+    assert_eq!(session.cursor(), (0, 2));
+    session.type_text("u");
+    assert_eq!(session.rows()[..3], ["zz", "third", ""]);
+}
+
+#[test]
+fn vim_huge_counts_are_cut_down() {
+    let huge = "99999999999999999999999";
+    let mut session = Session::with_vim("one two\nthree\nfour");
+
+    for motion in ["l", "w", "e", "j"] {
+        session.type_text(&format!("{}{}", huge, motion));
+    }
+    assert_eq!(session.cursor(), (3, 2));
+    for motion in ["h", "b", "k"] {
+        session.type_text(&format!("{}{}", huge, motion));
+    }
+    assert_eq!(session.cursor(), (0, 0));
+
+    session.type_text(&format!("{}x", huge));
+    assert_eq!(session.rows()[..3], ["", "three", "four"]);
+    session.type_text(&format!("u{}d{}w", huge, huge));
+    assert_eq!(session.rows()[..3], ["", "", ""]);
+
+    session.type_text(&format!("u{}dd", huge));
+    assert_eq!(session.rows()[..3], ["", "", ""]);
+    session.type_text("u");
+
+    session.type_text(&format!("yl{}p", huge));
+    assert_eq!(session.editor.storage().len(), "one two\nthree\nfour".len() + 10_000);
+}
+
+#[test]
+fn vim_dot_repeats_the_last_change() {
+    let mut session = Session::with_vim("one two three four five");
+
+    session.type_text("dw.");
+    assert_eq!(session.rows()[0], "three four five");
+
+    //Text typed in insert mode is part of the change
+    session.type_text("cwX");
+    session.press(vec![key(KeyCode::Esc)]);
+    session.type_text("w.");
+    assert_eq!(session.rows()[0], "X X five");
+    assert_eq!(session.cursor(), (2, 0));
+
+    session.type_text("onew");
+    session.press(vec![key(KeyCode::Esc)]);
+    session.type_text("2.");
+    assert_eq!(session.rows()[..4], ["X X five", "new", "new", "new"]);
+}
+
+#[test]
+fn vim_changes_are_undone_with_what_was_typed_after_them() {
+    let mut session = Session::with_vim("one\ntwo");
+
+    session.type_text("onew");
+    session.press(vec![key(KeyCode::Enter)]);
+    session.type_text("lines");
+    session.press(vec![key(KeyCode::Esc)]);
+    session.type_text("gg0Oabove");
+    session.press(vec![key(KeyCode::Esc)]);
+    session.type_text("jj0cwX");
+    session.press(vec![key(KeyCode::Esc)]);
+    assert_eq!(session.rows()[..5], ["above", "one", "X", "lines", "two"]);
+
+    session.type_text("u");
+    assert_eq!(session.rows()[..5], ["above", "one", "new", "lines", "two"]);
+    session.type_text("u");
+    assert_eq!(session.rows()[..4], ["one", "new", "lines", "two"]);
+    session.type_text("u");
+    assert_eq!(session.rows()[..3], ["one", "two", ""]);
+
+    session.press(vec![ctrl('r')]);
+    assert_eq!(session.rows()[..4], ["one", "new", "lines", "two"]);
+}
+
+#[test]
+fn vim_commands_that_stop_early_still_end_their_undo_step() {
+    let mut session = Session::with_vim("one");
+
+    session.type_text("vv");
+    session.press(vec![Event::Paste(String::from("A")), Event::Paste(String::from("B"))]);
+    assert_eq!(session.rows()[0], "ABone");
+
+    session.type_text("u");
+    assert_eq!(session.rows()[0], "Aone");
+}
+
+#[test]
+fn vim_dot_repeats_moves_made_in_insert_mode() {
+    let mut session = Session::with_vim("one\ntwo");
+
+    session.type_text("A()");
+    session.press(vec![key(KeyCode::Left)]);
+    session.type_text("x");
+    session.press(vec![key(KeyCode::Esc)]);
+    assert_eq!(session.rows()[0], "one(x)");
+
+    session.type_text("j.");
+    assert_eq!(session.rows()[..2], ["one(x)", "two(x)"]);
+
+    //Repeated changes are one undo step as well
+    session.type_text("u");
+    assert_eq!(session.rows()[..2], ["one(x)", "two"]);
+}
+
+#[test]
+fn vim_text_objects_pick_words_quotes_parens_and_paragraphs() {
+    let mut session = Session::with_vim("say(\"hi there\", (a b)) now\n\npara one\npara two");
+
+    session.type_text("wci\"yo");
+    session.press(vec![key(KeyCode::Esc)]);
+    assert_eq!(session.rows()[0], "say(\"yo\", (a b)) now");
+
+    //The innermost parens around the cursor, nested ones included
+    session.type_text("di(");
+    assert_eq!(session.rows()[0], "say() now");
+    session.type_text("u$diw");
+    assert_eq!(session.rows()[0], "say(\"yo\", (a b))");
+
+    session.type_text("Gdip");
+    assert_eq!(session.rows()[..3], ["say(\"yo\", (a b))", "", ""]);
+}
+
+#[test]
+fn vim_visual_modes_select_and_operate() {
+    let mut session = Session::with_vim("one two three\nfour\nfive");
+
+    session.type_text("ve");
+    assert_eq!(session.selected()[0], "one");
+    assert!(session.status().contains("-- VISUAL --"));
+    session.type_text("d");
+    assert_eq!(session.rows()[0], " two three");
+    assert!(!session.status().contains("VISUAL"));
+
+    session.type_text("wviw");
+    assert_eq!(session.selected()[0], "two");
+    session.type_text("y");
+    assert_eq!(session.selected()[0], "");
+
+    session.type_text("Vj");
+    assert!(session.status().contains("-- VISUAL LINE --"));
+    session.type_text("d");
+    assert_eq!(session.rows()[..2], ["five", ""]);
+    session.type_text("P");
+    assert_eq!(session.rows()[..3], [" two three", "four", "five"]);
+}
+
+#[test]
+fn tabs_take_four_columns() {
+    let mut session = Session::new();
+    session.type_text("\tx");
+
+    assert_eq!(session.rows()[0], "    x");
+    assert_eq!(session.cursor(), (5, 0));
+}
+
+#[test]
+fn multibyte_chars_take_one_column() {
+    let mut session = Session::new();
+    session.type_text("héllo wörld");
+    session.press(repeat(key(KeyCode::Left), 4));
+    session.type_text("_");
+
+    assert_eq!(session.rows()[0], "héllo w_örld");
+    assert_eq!(session.cursor(), (8, 0));
+}
+
+#[test]
+fn wide_chars_take_two_columns() {
+    let mut session = Session::new();
+    session.type_text("a日本b");
+    assert_eq!(session.rows()[0], "a日本b");
+    assert_eq!(session.cursor(), (6, 0));
+
+    session.press(repeat(key(KeyCode::Left), 2));
+    assert_eq!(session.cursor(), (3, 0));
+    session.type_text("_");
+    assert_eq!(session.rows()[0], "a日_本b");
+    assert_eq!(session.screen.cell(MARGIN + 1, MARGIN).ch, '日');
+    assert_eq!(session.screen.cell(MARGIN + 2, MARGIN).ch, WIDE_TAIL);
+    assert_eq!(session.screen.cell(MARGIN + 3, MARGIN).ch, '_');
+
+    //Up and down never land in the middle of a wide char, like with tabs
+    session.type_text("\nxx");
+    session.press(vec![key(KeyCode::Up)]);
+    assert_eq!(session.cursor(), (3, 0));
+
+    session.press(vec![key(KeyCode::Home), key_with(KeyCode::Right, KeyModifiers::SHIFT)]);
+    session.press(vec![key_with(KeyCode::Right, KeyModifiers::SHIFT)]);
+    assert_eq!(session.selected()[0], "a日");
+}
+
+#[test]
+fn wide_chars_wrap_as_a_whole() {
+    let mut session = Session::new();
+    session.type_text(&"日".repeat(14));
+
+    assert_eq!(session.rows()[..2], ["日".repeat(13), "日".to_string()]);
+    assert_eq!(session.cursor(), (2, 1));
+
+    session.press(vec![key(KeyCode::Up), key(KeyCode::Home)]);
+    session.type_text("x");
+    assert_eq!(session.rows()[..2], [format!("x{}", "日".repeat(12)), "日日".to_string()]);
+}
+
+#[test]
+fn wide_chars_are_printed_once_and_overwritten_whole() {
+    let mut out = Vec::new();
+    let mut backend = CrosstermBackend::new(&mut out);
+    let mut screen = Screen::new(4, 1);
+    screen.print(0, 0, "日x本", Color::Reset, Color::Reset);
+    screen.flush(&mut backend).unwrap();
+
+    //The wide char that no longer fits at the edge is left out, and nothing is printed for
+    //the cell it covers
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.contains("日x "));
+    assert!(!text.contains(WIDE_TAIL));
+
+    let mut memory = MemoryBackend::new(4, 1);
+    let mut screen = Screen::new(4, 1);
+    screen.print(0, 0, "日x", Color::Reset, Color::Reset);
+    screen.print(1, 0, "a", Color::Reset, Color::Reset);
+    screen.flush(&mut memory).unwrap();
+    assert_eq!(memory.row_text(0), " ax");
+}
+
+#[test]
+fn control_chars_are_shown_as_placeholders() {
+    let text = "a\x1b[2Jb\0c\x7f\u{85}";
+    let mut session = Session::new();
+    session.press(vec![Event::Paste(String::from(text))]);
+    assert_eq!(session.rows()[0], "a\u{fffd}[2Jb\u{fffd}c\u{fffd}\u{fffd}");
+    assert_eq!(session.cursor(), (10, 0));
+
+    //Nothing of them reaches the terminal
+    let mut out = Vec::new();
+    let mut screen = Screen::new(12, 1);
+    screen.print(0, 0, text, Color::Reset, Color::Reset);
+    screen.flush(&mut CrosstermBackend::new(&mut out)).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(!out.contains("\x1b[2J"));
+    assert!(!out.contains(['\0', '\x7f', '\u{85}']));
+    assert_eq!(out.matches(CONTROL).count(), 4);
+}
+
+#[test]
+fn undo_and_redo_update_the_screen() {
+    let mut session = Session::new();
+    session.type_text("hello");
+
+    session.press(vec![ctrl('z')]);
+    assert_eq!(session.rows()[0], "");
+    assert_eq!(session.cursor(), (0, 0));
+
+    session.press(vec![ctrl('y')]);
+    assert_eq!(session.rows()[0], "hello");
+    assert_eq!(session.cursor(), (5, 0));
+}
+
+#[test]
+fn scrolls_to_keep_the_cursor_visible() {
+    let mut session = Session::new();
+    let lines: Vec<String> = (1..=12).map(|i| i.to_string()).collect();
+    session.type_text(&lines.join("\n"));
+
+    assert_eq!(session.rows(), lines[4..]);
+    assert_eq!(session.cursor(), (2, 7));
+
+    session.press(repeat(key(KeyCode::Up), 8));
+    assert_eq!(session.rows(), lines[3..11]);
+    assert_eq!(session.cursor(), (1, 0));
+}
+
+#[test]
+fn resizing_rewraps_the_text() {
+    let mut session = Session::new();
+    session.type_text("the quick brown fox jumps over the lazy dog");
+    session.press(vec![Event::Resize(20, 12)]);
+
+    //16 columns are left for the text now
+    assert_eq!(session.screen.row_text(MARGIN).trim_start(), "the quick brown");
+    assert_eq!(session.screen.row_text(MARGIN + 1).trim_start(), "fox jumps over");
+    assert_eq!(session.screen.row_text(MARGIN + 2).trim_start(), "the lazy dog");
+    assert_eq!(session.screen.cursor(), (MARGIN + 12, MARGIN + 2));
+}
+
+#[test]
+fn widening_after_scrolling_through_wrapped_text_keeps_the_cursor_in_view() {
+    let mut session = Session::new();
+    let line = "the quick brown fox jumps over";
+    let lines: Vec<&str> = (0..20).map(|_| line).collect();
+    session.type_text(&lines.join("\n"));
+    session.press(repeat(key(KeyCode::Up), 9));
+
+    //Every line fits on one row now, fewer than were scrolled past before
+    session.press(vec![Event::Resize(60, 12)]);
+    let row = |row| session.screen.row_text(row).trim_start().to_string();
+    assert_eq!(row(MARGIN), line);
+    assert_eq!(row(MARGIN + 7), line);
+    let (_, cursor_row) = session.screen.cursor();
+    assert!((MARGIN..MARGIN + 8).contains(&cursor_row));
+
+    session.press(vec![key_with(KeyCode::End, KeyModifiers::CONTROL)]);
+    assert_eq!(session.screen.cursor(), (MARGIN + 30, MARGIN + 7));
+}
+
+#[test]
+fn title_marks_unsaved_changes() {
+    let mut session = Session::new();
+    assert_eq!(session.title().trim(), "[Test] [No Name]");
+
+    session.type_text("a");
+    assert_eq!(session.title().trim(), "[Test] [No Name] [+]");
+}
+
+#[test]
+fn quitting_with_unsaved_changes_asks_first() {
+    let mut session = Session::new();
+    assert!(session.quits(vec![ctrl('q')]));
+
+    session.type_text("a");
+    assert!(!session.quits(vec![ctrl('q'), key(KeyCode::Char('n'))]));
+    assert!(!session.status().contains("Quit"));
+    assert!(session.quits(vec![ctrl('q'), key(KeyCode::Char('y'))]));
+}
+
+//Names in `dir` other than the undo file every save writes next to the file
+fn files_in(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.ends_with(".undo"))
+        .collect();
+    names.sort();
+    names
+}
+
+#[cfg(unix)]
+#[test]
+fn saving_keeps_the_mode_and_leaves_no_temporary_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_dir("save");
+    let path = dir.join("secret.txt");
+    std::fs::write(&path, "saved").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+    let mut session = Session::open(&path);
+    session.type_text("new ");
+    session.editor.save().unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    let files = files_in(&dir);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(contents, "new saved");
+    assert_eq!(mode & 0o777, 0o600);
+    assert_eq!(files, ["secret.txt"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn saving_with_backup_keeps_the_old_contents() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_dir("backup");
+    let path = dir.join("secret.txt");
+    std::fs::write(&path, "saved").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+    let mut session = Session::open(&path);
+    session.editor.set_backup(true);
+    session.type_text("new ");
+    session.editor.save().unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let backup = std::fs::read_to_string(dir.join("secret.txt~")).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    let files = files_in(&dir);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(contents, "new saved");
+    assert_eq!(backup, "saved");
+    assert_eq!(mode & 0o777, 0o600);
+    assert_eq!(files, ["secret.txt", "secret.txt~"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn saving_through_a_symlink_keeps_the_link() {
+    let dir = test_dir("symlink");
+    let target = dir.join("target.txt");
+    let link = dir.join("link.txt");
+    std::fs::write(&target, "saved").unwrap();
+    std::os::unix::fs::symlink(&target, &link).unwrap();
+
+    let mut session = Session::open(&link);
+    session.type_text("new ");
+    session.editor.save().unwrap();
+    let is_link = std::fs::symlink_metadata(&link).unwrap().file_type().is_symlink();
+    let contents = std::fs::read_to_string(&target).unwrap();
+    let files = files_in(&dir);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(is_link);
+    assert_eq!(contents, "new saved");
+    assert_eq!(files, ["link.txt", "target.txt"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn swap_files_keep_the_permissions_of_the_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_dir("swap");
+    let path = dir.join("secret.txt");
+    std::fs::write(&path, "saved").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+    let swap_path = swap::swap_path(&path).unwrap();
+    swap::write(&swap_path, &path, "unsaved").unwrap();
+    let mode = std::fs::metadata(&swap_path).unwrap().permissions().mode();
+    let swap_info = swap::read(&swap_path).unwrap().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(mode & 0o777, 0o600);
+    assert_eq!(swap_info.contents, "unsaved");
+    //Our own pid in a swap file was left behind by an earlier process that had it
+    assert!(swap_info.is_stale());
+}
+
+#[test]
+fn huge_time_travels_are_rejected_or_clamped() {
+    let mut session = Session::new();
+    session.type_text("a");
+
+    let mut events = vec![ctrl('t')];
+    events.extend(keys("-999999999999999999d\n"));
+    session.press(events);
+    assert!(session.status().contains("Invalid travel"));
+
+    let mut events = vec![ctrl('t')];
+    events.extend(keys(&format!("+{}\n", i64::MAX)));
+    session.press(events);
+    assert_eq!(session.rows()[0], "a");
+    assert!(session.status().contains("Nothing to travel to"));
+}
+
+//A fresh directory for files of the test `name`
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("text-editor-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn undoing_back_to_the_saved_text_clears_the_unsaved_mark() {
+    let dir = test_dir("saved-state");
+    let path = dir.join("notes.txt");
+    std::fs::write(&path, "saved").unwrap();
+    //The title is too narrow for the whole path, so the flag is checked instead of the `[+]`
+    let mut session = Session::open(&path);
+
+    session.type_text("!");
+    session.press(vec![ctrl('s')]);
+    assert!(!session.editor.is_modified());
+    session.type_text("?");
+    assert!(session.editor.is_modified());
+
+    session.press(vec![ctrl('z')]);
+    assert!(!session.editor.is_modified());
+    session.press(vec![ctrl('z')]);
+    assert!(session.editor.is_modified());
+    session.press(vec![ctrl('y')]);
+    assert!(!session.editor.is_modified());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn recovering_a_swap_file_starts_a_new_undo_history() {
+    let dir = test_dir("recover");
+    let path = dir.join("notes.txt");
+    std::fs::write(&path, "one").unwrap();
+
+    //A saved session leaves an undo history for the text on disk behind
+    let mut session = Session::open(&path);
+    session.type_text(" two");
+    session.press(vec![ctrl('s')]);
+    let swap_path = swap::swap_path(&path).unwrap();
+    swap::write(&swap_path, &path, "recovered text").unwrap();
+
+    let mut session = Session::open(&path);
+    let swap_info = session.editor.find_swap().unwrap().unwrap();
+    session.editor.recover_swap(swap_info);
+    session.press(vec![ctrl('z')]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(session.rows()[0], "recovered text");
+    assert!(session.status().contains("Already at oldest change"));
+    assert!(session.editor.is_modified());
+}
+
+#[test]
+fn undo_files_of_an_older_version_are_not_loaded() {
+    let dir = test_dir("undo-version");
+    let path = dir.join("notes.txt");
+    let mut session = Session::open(&path);
+    session.type_text("héllo");
+    session.press(vec![ctrl('s')]);
+
+    //The same history, but claiming to count positions in chars like version 1 did
+    let undo_path = undo_file::undo_path(&path).unwrap();
+    let data = std::fs::read_to_string(&undo_path).unwrap();
+    let (_, rest) = data.split_once('\n').unwrap();
+    std::fs::write(&undo_path, format!("text-editor undo 1\n{}", rest)).unwrap();
+
+    let mut session = Session::open(&path);
+    session.press(vec![ctrl('z')]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(session.rows()[0], "héllo");
+    assert!(session.status().contains("Already at oldest change"));
+}
+
+#[test]
+fn bookmarks_follow_the_text() {
+    let mut session = Session::new();
+    session.type_text("abc");
+    session.press([vec![ctrl('k')], keys("m\n")].concat());
+    assert_eq!(session.status().trim(), "=== Mark 'm' set");
+
+    session.press(repeat(key(KeyCode::Left), 3));
+    session.type_text("XY");
+    session.press([vec![ctrl('g')], keys("m\n")].concat());
+
+    assert_eq!(session.rows()[0], "XYabc");
+    assert_eq!(session.cursor(), (5, 0));
+}
+
+#[test]
+fn marks_move_with_inserts_by_their_gravity() {
+    let mut marks = Marks::new();
+    let left = marks.add(3, Gravity::Left);
+    let right = marks.add(3, Gravity::Right);
+    let before = marks.add(2, Gravity::Right);
+    let after = marks.add(4, Gravity::Left);
+
+    marks.insert(3, 5);
+    assert_eq!(marks.get(left), Some(3));
+    assert_eq!(marks.get(right), Some(8));
+    assert_eq!(marks.get(before), Some(2));
+    assert_eq!(marks.get(after), Some(9));
+}
+
+#[test]
+fn marks_in_deleted_text_collapse_to_its_start() {
+    let mut marks = Marks::new();
+    let at_start = marks.add(2, Gravity::Right);
+    let inside = marks.add(4, Gravity::Left);
+    let at_end = marks.add(6, Gravity::Left);
+    let after = marks.add(9, Gravity::Right);
+    let before = marks.add(1, Gravity::Left);
+
+    marks.delete(2..6);
+    assert_eq!(marks.get(at_start), Some(2));
+    assert_eq!(marks.get(inside), Some(2));
+    assert_eq!(marks.get(at_end), Some(2));
+    assert_eq!(marks.get(after), Some(5));
+    assert_eq!(marks.get(before), Some(1));
+
+    //Removed marks are gone, their slot goes to the next one
+    marks.remove(inside);
+    assert_eq!(marks.get(inside), None);
+    let reused = marks.add(0, Gravity::Left);
+    assert_eq!(reused, inside);
+}
+
+#[test]
+fn storages_render_the_same() {
+    let script = |session: &mut Session| {
+        session.type_text("fn main() {\n\tprintln!(\"hi\");\n}\n");
+        session.press(repeat(key(KeyCode::Up), 2));
+        session.type_text("let x = 1;\n\t");
+        session.press(repeat(key(KeyCode::Backspace), 3));
+        session.press(vec![ctrl('z')]);
+    };
+
+    let mut expected = Session::new();
+    script(&mut expected);
+    for storage in StorageKind::ALL {
+        let mut session = Session::with_storage(storage);
+        script(&mut session);
+        assert_eq!(session.screen.text(), expected.screen.text(), "{}", storage.name());
+        assert_eq!(session.cursor(), expected.cursor(), "{}", storage.name());
+    }
+}
+
+#[test]
+fn recordings_replay_the_same_events() {
+    let events = vec![
+        key(KeyCode::Char(' ')),
+        key(KeyCode::Char('\\')),
+        ctrl('s'),
+        key(KeyCode::F(5)),
+        Event::Key(KeyEvent::new(KeyCode::Left, KeyModifiers::SHIFT | KeyModifiers::ALT)),
+        Event::Paste(String::from("two\nlines ")),
+        Event::Mouse(MouseEvent {
+            kind: MouseEventKind::Drag(MouseButton::Left),
+            column: 4,
+            row: 7,
+            modifiers: KeyModifiers::NONE,
+        }),
+        Event::Resize(100, 40),
+        Event::FocusLost,
+    ];
+
+    let path = std::env::temp_dir().join(format!("text-editor-{}.rec", std::process::id()));
+    let keys = Keys(VecDeque::from(events.clone()));
+    let mut recorder = Recorder::create(&path, Box::new(keys), (30, 12)).unwrap();
+    while !recorder.finished() {
+        recorder.read().unwrap();
+    }
+
+    let mut replay = Replay::open(&path, true).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut replayed = Vec::new();
+    while !replay.finished() {
+        replayed.push(replay.read().unwrap());
+    }
+
+    assert_eq!(replay.size, (30, 12));
+    assert_eq!(replayed, events);
+}
+
+#[test]
+fn logs_rotate_once_they_grow_too_large() {
+    let dir = std::env::temp_dir().join(format!("text-editor-logs-{}", std::process::id()));
+    let path = dir.join("nested").join("log.txt");
+    let logger = FileLogger::open(&path, LevelFilter::Info, 200).unwrap();
+    let log = |level: Level, text: &str| {
+        logger.log(&Record::builder().level(level).args(format_args!("{}", text)).build());
+    };
+
+    //Each line is about 70 bytes, so two of them fit in a file
+    for i in 0..5 {
+        log(Level::Info, &format!("line {} {}", i, "x".repeat(40)));
+    }
+    log(Level::Debug, "too detailed");
+
+    let read = |name: &str| std::fs::read_to_string(dir.join("nested").join(name)).unwrap();
+    let (current, first, second) = (read("log.txt"), read("log.txt.1"), read("log.txt.2"));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(current.contains("line 4") && !current.contains("line 3"));
+    assert!(first.contains("line 2") && first.contains("line 3"));
+    assert!(second.contains("line 0") && second.contains("line 1"));
+    assert!(!current.contains("too detailed"));
+}
+
+//Xorshift, the same seed makes the same edits on every run
+struct Rng(u64);
+
+impl Rng {
+    fn below(self: &mut Self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+
+    //A char boundary of `text`, its end included
+    fn boundary(self: &mut Self, text: &str) -> usize {
+        let boundaries = text.char_indices().map(|(i, _)| i).chain([text.len()]);
+        let boundaries = boundaries.collect::<Vec<usize>>();
+        boundaries[self.below(boundaries.len())]
+    }
+}
+
+const SAMPLES: [&str; 8] = ["a", "bc", "\n", "é", "日本", "🦀\n", "line\nbreak\n", "\t"];
+
+//Makes random edits to `storage` and to a `String`, checking that they agree after each one
+fn check_against_model(storage: &mut dyn TextStorage, seed: u64, edits: usize) {
+    let mut rng = Rng(seed);
+    let mut model = storage.text();
+    for _ in 0..edits {
+        if model.is_empty() || rng.below(3) < 2 {
+            let pos = rng.boundary(&model);
+            let text = SAMPLES[rng.below(SAMPLES.len())].repeat(1 + rng.below(4));
+            storage.insert_str(pos, &text);
+            model.insert_str(pos, &text);
+        } else {
+            let (a, b) = (rng.boundary(&model), rng.boundary(&model));
+            let range = a.min(b)..a.max(b);
+            storage.delete_range(range.clone());
+            model.replace_range(range, "");
+        }
+        assert_matches_model(storage, &model);
+    }
+}
+
+fn assert_matches_model(storage: &dyn TextStorage, model: &str) {
+    assert_eq!(storage.text(), model);
+    assert_eq!(storage.len(), model.len());
+
+    let line_starts = std::iter::once(0)
+        .chain(model.match_indices('\n').map(|(i, _)| i + 1))
+        .collect::<Vec<usize>>();
+    assert_eq!(storage.line_count(), line_starts.len());
+    for (line, &start) in line_starts.iter().enumerate() {
+        assert_eq!(storage.line_to_byte(line), start);
+    }
+    assert_eq!(storage.line_to_byte(line_starts.len()), model.len());
+
+    let boundaries = model.char_indices().map(|(i, _)| i).chain([model.len()]);
+    for (chars, pos) in boundaries.enumerate() {
+        let line = line_starts.partition_point(|&start| start <= pos) - 1;
+        assert_eq!(storage.byte_to_line(pos), line);
+        assert_eq!(storage.byte_to_char(pos), chars);
+        assert_eq!(storage.char_to_byte(chars), pos);
+        assert!(storage.is_char_boundary(pos));
+        assert!(storage.chars_at(pos).eq(model[pos..].chars()));
+        assert!(storage.chars_rev_at(pos).eq(model[..pos].chars().rev()));
+    }
+}
+
+#[test]
+fn gap_buffer_edits_match_a_string() {
+    for seed in 1..=4 {
+        check_against_model(&mut TextGapBuffer::new(), seed, 150);
+    }
+
+    //A tiny buffer has to grow and widen its gap on almost every edit
+    check_against_model(&mut TextGapBuffer::new_in(1, 1), 5, 150);
+}
+
+#[test]
+fn gap_buffer_window_moves_keep_the_text() {
+    let mut rng = Rng(7);
+    let mut model = String::from("héllo\nwörld\n🦀");
+    let mut buffer = TextGapBuffer::new_in(4, 2);
+    buffer.insert_str(0, &model);
+
+    for _ in 0..100 {
+        let pos = rng.boundary(&model);
+        buffer.move_window(pos);
+        assert_matches_model(&buffer, &model);
+
+        //Chars typed and deleted at the gap land where it was moved to
+        let ch = SAMPLES[rng.below(SAMPLES.len())].chars().next().unwrap();
+        buffer.insert_ch(ch);
+        model.insert(pos, ch);
+        if rng.below(2) == 0 {
+            buffer.delete_ch();
+            model.remove(pos);
+        } else if pos + ch.len_utf8() < model.len() {
+            buffer.delete_next_ch();
+            model.remove(pos + ch.len_utf8());
+        }
+        assert_matches_model(&buffer, &model);
+    }
+}
+
+#[test]
+fn gap_buffer_reports_misuse() {
+    let mut buffer = TextGapBuffer::from_text("é");
+
+    assert_eq!(
+        buffer.try_insert_str(3, "x"),
+        Err(BufferError::OutOfRange { pos: 3, len: 2 })
+    );
+    assert_eq!(buffer.try_insert_str(1, "x"), Err(BufferError::InvalidCharBoundary(1)));
+    assert_eq!(buffer.try_delete_range(0..1), Err(BufferError::InvalidCharBoundary(1)));
+    assert_eq!(buffer.try_move_window(1), Err(BufferError::InvalidCharBoundary(1)));
+    assert_eq!(buffer.get(2), Err(BufferError::OutOfRange { pos: 2, len: 2 }));
+    assert!(buffer.try_slice(0..1).is_err());
+    #[allow(clippy::reversed_empty_ranges)]
+    let reversed = buffer.try_slice(2..0);
+    assert_eq!(reversed, Err(BufferError::OutOfRange { pos: 2, len: 0 }));
+    assert_eq!(buffer.to_string(), "é");
+
+    //Deleting past the ends is an error only for the `try_` methods
+    buffer.move_window(0);
+    assert!(buffer.try_delete_ch().is_err());
+    buffer.delete_ch();
+    buffer.move_window(2);
+    assert!(buffer.try_delete_next_ch().is_err());
+    buffer.delete_next_ch();
+    assert_eq!(buffer.try_delete_ch(), Ok('é'));
+    assert_eq!(buffer.to_string(), "");
+}
+
+#[test]
+#[should_panic]
+fn gap_buffer_slices_panic_on_reversed_ranges() {
+    let buffer = TextGapBuffer::from_text("ab");
+    #[allow(clippy::reversed_empty_ranges)]
+    buffer.slice(2..1);
+}
+
+#[test]
+fn rope_and_piece_table_edits_match_a_string() {
+    for seed in 1..=4 {
+        check_against_model(&mut Rope::from(""), seed, 150);
+        check_against_model(&mut PieceTable::from("loaded\ntext é\n"), seed, 150);
+    }
+}
+
+#[test]
+fn ropes_split_collapse_and_rebalance_under_many_edits() {
+    let mut model = "0123456789\n".repeat(400);
+    let mut rope = Rope::from(model.as_str());
+    let mut rng = Rng(11);
+
+    //Typing in one spot keeps splitting the same leaf, which makes the tree lopsided
+    for i in 0..3000 {
+        let text = SAMPLES[i % SAMPLES.len()];
+        rope.insert_str(2200, text);
+        model.insert_str(2200, text);
+    }
+    assert_eq!(rope.text(), model);
+
+    //Deleting most of it again empties leaves, which get merged away
+    while model.len() > 1500 {
+        let start = rng.boundary(&model[..model.len() - 1000]);
+        let end = start + model[start..].char_indices().nth(700).unwrap().0;
+        rope.delete_range(start..end);
+        model.replace_range(start..end, "");
+        assert_eq!(rope.len(), model.len());
+    }
+    assert_matches_model(&rope, &model);
+}
+
+#[test]
+fn rope_and_piece_table_report_misuse() {
+    let storages: [Box<dyn TextStorage>; 2] =
+        [Box::new(Rope::from("é")), Box::new(PieceTable::from("é"))];
+    for mut storage in storages {
+        assert_eq!(
+            storage.try_insert_str(3, "x"),
+            Err(BufferError::OutOfRange { pos: 3, len: 2 })
+        );
+        assert_eq!(storage.try_insert_str(1, "x"), Err(BufferError::InvalidCharBoundary(1)));
+        assert_eq!(storage.try_delete_range(0..1), Err(BufferError::InvalidCharBoundary(1)));
+        assert_eq!(
+            storage.try_delete_range(0..5),
+            Err(BufferError::OutOfRange { pos: 5, len: 2 })
+        );
+        assert!(storage.get(2).is_err());
+        assert_eq!(storage.text(), "é");
+    }
+}
+
+#[test]
+fn line_index_follows_random_edits() {
+    let mut rng = Rng(21);
+    let mut model = String::new();
+    let mut index = LineIndex::new();
+
+    for _ in 0..300 {
+        if model.is_empty() || rng.below(3) < 2 {
+            let pos = rng.boundary(&model);
+            let text = SAMPLES[rng.below(SAMPLES.len())].repeat(1 + rng.below(4));
+            index.insert(pos, &text);
+            model.insert_str(pos, &text);
+        } else {
+            let (a, b) = (rng.boundary(&model), rng.boundary(&model));
+            index.delete(a.min(b)..a.max(b));
+            model.replace_range(a.min(b)..a.max(b), "");
+        }
+
+        let starts = std::iter::once(0)
+            .chain(model.match_indices('\n').map(|(i, _)| i + 1))
+            .collect::<Vec<usize>>();
+        assert_eq!(index.line_count(), starts.len());
+        for (line, &start) in starts.iter().enumerate() {
+            assert_eq!(index.line_start(line), start);
+        }
+        assert_eq!(index.line_start(starts.len()), model.len());
+        for pos in 0..=model.len() {
+            assert_eq!(index.line_of(pos), starts.partition_point(|&start| start <= pos) - 1);
+        }
+    }
+}
+
+#[test]
+fn line_map_replaces_rows_and_moves_the_ones_after() {
+    let row = |index: usize| LineInfo {
+        index,
+        len: 0,
+        width: 0,
+    };
+    let mut rng = Rng(33);
+    let mut model = (0..20).map(|i| i * 5).collect::<Vec<usize>>();
+    let mut text_len = 100;
+    let mut map = LineMap::new(model.iter().map(|&index| row(index)).collect(), text_len);
+
+    for _ in 0..300 {
+        //Rows `a..b` cover the bytes `start..end` and are laid out again for `span` new bytes
+        let a = rng.below(model.len() + 1);
+        let b = a + rng.below(model.len() - a + 1);
+        let start = model.get(a).copied().unwrap_or(text_len);
+        let end = model.get(b).copied().unwrap_or(text_len);
+        let span = rng.below(20);
+        let new_rows = (start..start + span).take(rng.below(4)).collect::<Vec<usize>>();
+
+        text_len = text_len + span - (end - start);
+        let moved = model[b..].iter().map(|index| index + span - (end - start));
+        model = model[..a].iter().copied().chain(new_rows.clone()).chain(moved).collect();
+        map.replace(a..b, new_rows.into_iter().map(row).collect(), text_len);
+
+        assert_eq!(map.len(), model.len());
+        for (i, &index) in model.iter().enumerate() {
+            assert_eq!(map.get(i).index, index);
+        }
+        for index in 0..=text_len {
+            let found = model.partition_point(|&start| start <= index).saturating_sub(1);
+            assert_eq!(map.find(index), found);
+        }
+    }
+}
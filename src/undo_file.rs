@@ -0,0 +1,53 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::file;
+use crate::history::History;
+
+//Version 2 stores byte positions. Version 1 files stored char positions and are not loaded.
+const UNDO_MAGIC: &str = "text-editor undo 2";
+
+//`dir/file.txt` keeps its edit history in `dir/.file.txt.undo`
+pub fn undo_path(path: &Path) -> std::io::Result<PathBuf> {
+    file::sibling_path(path, |name| format!(".{}.undo", name))
+}
+
+//FNV-1a, unlike `DefaultHasher` it is guaranteed to stay the same across Rust releases
+pub fn content_hash(contents: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in contents.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+//Stores `history` for a file whose saved contents hash to `hash`
+pub fn write(undo_path: &Path, hash: u64, history: &History) -> std::io::Result<()> {
+    let data = format!("{}\nhash {:016x}\n{}", UNDO_MAGIC, hash, history.to_text());
+    file::write_atomic(undo_path, data.as_bytes(), false)
+}
+
+//Loads the history only when it was written for exactly these file contents,
+//anything else means the file changed behind our back and the history no longer applies.
+pub fn read(undo_path: &Path, hash: u64) -> std::io::Result<Option<History>> {
+    let data = match fs::read_to_string(undo_path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let mut parts = data.splitn(3, '\n');
+    if parts.next() != Some(UNDO_MAGIC) {
+        return Ok(None);
+    }
+
+    match parts.next() {
+        Some(line) if line == format!("hash {:016x}", hash) => {}
+        _ => return Ok(None),
+    }
+
+    Ok(parts.next().and_then(History::from_text))
+}
@@ -0,0 +1,1014 @@
+use std::cmp::min;
+use std::ops::Range;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::editor::{Direction, Editor, SelectionKind};
+use crate::storage::TextStorage;
+use crate::words::{self, CharClass, WordChars};
+
+//Modal editing on top of the editor, turned on with `--vim`. Keys are collected until they
+//make up a whole command, like `2d3w`, and then run against the buffer. Insert mode hands the
+//keys back to the usual key handling.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Motion {
+    Left,
+    Right,
+    Down,
+    Up,
+    WordStart,
+    WordBack,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    FirstLine,
+    LastLine,
+}
+
+impl Motion {
+    fn linewise(self: &Self) -> bool {
+        matches!(
+            self,
+            Motion::Down | Motion::Up | Motion::FirstLine | Motion::LastLine
+        )
+    }
+
+    //Whether the char the motion ends on is part of what an operator works on
+    fn inclusive(self: &Self) -> bool {
+        *self == Motion::WordEnd
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Object {
+    Word,
+    Quotes,
+    Parens,
+    Paragraph,
+}
+
+enum Target {
+    Motion(Motion),
+    Object(Object),
+    //`dd`, `cc` and `yy`
+    Lines,
+}
+
+enum Command {
+    Move(Motion),
+    Operate(Operator, Target),
+    //An operator typed in visual mode, working on the selection
+    OperateSelection(Operator),
+    //A text object typed in visual mode, selecting it
+    Select(Object),
+    Action(char),
+}
+
+//Counts are cut down to this, repeating anything more often than that only takes long
+const MAX_COUNT: usize = 10_000;
+
+enum Parse<T> {
+    Done(T),
+    Incomplete,
+    Invalid,
+}
+
+//The last change, for `.`: the keys of the command and the ones typed in insert mode after it,
+//moves included
+#[derive(Clone)]
+struct Change {
+    keys: String,
+    inserted: Option<Vec<KeyCode>>,
+}
+
+pub struct Vim {
+    mode: Mode,
+    //Keys of the command typed so far
+    pending: String,
+    //Column kept on `j` and `k` across shorter lines
+    want_column: Option<usize>,
+    last_change: Option<Change>,
+    //Keys of the command that started insert mode and the ones typed since
+    insert_change: Option<Change>,
+    replaying: bool,
+    //What the status bar was last set to
+    shown: String,
+}
+
+impl Vim {
+    pub fn new() -> Self {
+        Vim {
+            mode: Mode::Normal,
+            pending: String::new(),
+            want_column: None,
+            last_change: None,
+            insert_change: None,
+            replaying: false,
+            shown: String::new(),
+        }
+    }
+
+    //Runs `key` as a vim command, false when it should go through the usual key handling
+    //instead, which is the case in insert mode and for Ctrl and Alt shortcuts
+    pub fn handle_key(self: &mut Self, editor: &mut Editor, key: KeyEvent) -> bool {
+        if key.modifiers.contains(KeyModifiers::ALT) {
+            return false;
+        }
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            if self.mode == Mode::Insert || key.code != KeyCode::Char('r') {
+                return false;
+            }
+
+            editor.redo();
+            self.clamp_cursor(editor);
+            return true;
+        }
+
+        let handled = match self.mode {
+            Mode::Insert => self.insert_key(editor, key),
+            _ => {
+                self.command_key(editor, key);
+                true
+            }
+        };
+        self.show_mode(editor);
+        handled
+    }
+
+    fn insert_key(self: &mut Self, editor: &mut Editor, key: KeyEvent) -> bool {
+        if key.code == KeyCode::Esc {
+            if let Some(change) = self.insert_change.take() {
+                self.last_change = Some(change);
+            }
+            self.mode = Mode::Normal;
+            if !self.replaying {
+                editor.end_undo_step();
+            }
+
+            //The cursor goes back onto the last char typed
+            let index = editor.cursor_index();
+            let storage = editor.storage();
+            if index > line_start(storage, line_of(storage, index)) {
+                editor.move_to_index(storage.prev_char_boundary(index));
+            }
+            return true;
+        }
+
+        //Shift with anything but a char selects
+        let plain = key.modifiers.is_empty() || matches!(key.code, KeyCode::Char(_));
+        let inserted = self
+            .insert_change
+            .as_mut()
+            .and_then(|change| change.inserted.as_mut());
+        if let Some(inserted) = inserted {
+            if plain && repeatable(key.code) {
+                inserted.push(key.code);
+            }
+        }
+
+        false
+    }
+
+    fn command_key(self: &mut Self, editor: &mut Editor, key: KeyEvent) {
+        if key.code == KeyCode::Esc {
+            self.pending.clear();
+            if self.mode != Mode::Normal {
+                self.leave_visual(editor);
+            }
+            return;
+        }
+
+        let ch = match key_char(key.code) {
+            Some(ch) => ch,
+            None => return self.pending.clear(),
+        };
+        self.pending.push(ch);
+
+        let visual = self.mode != Mode::Normal;
+        match parse(&self.pending, visual) {
+            Parse::Incomplete => {}
+            Parse::Invalid => self.pending.clear(),
+            Parse::Done((count, command)) => {
+                let keys = std::mem::take(&mut self.pending);
+                self.run(editor, count, command, keys);
+            }
+        }
+    }
+
+    fn run(
+        self: &mut Self,
+        editor: &mut Editor,
+        count: Option<usize>,
+        command: Command,
+        keys: String,
+    ) {
+        let changes = match &command {
+            Command::Operate(operator, _) => *operator != Operator::Yank,
+            Command::Action(action) => self.mode == Mode::Normal && "xpPoOiaIA".contains(*action),
+            _ => false,
+        };
+        if !matches!(command, Command::Move(Motion::Down | Motion::Up)) {
+            self.want_column = None;
+        }
+
+        //A command is one undo step, together with what is typed after it in insert mode
+        if !self.replaying {
+            editor.begin_undo_step();
+        }
+
+        self.execute(editor, count, command);
+
+        if changes && !self.replaying {
+            let inserted = if self.mode == Mode::Insert {
+                Some(Vec::new())
+            } else {
+                None
+            };
+            let change = Change { keys, inserted };
+            if self.mode == Mode::Insert {
+                self.insert_change = Some(change);
+            } else {
+                self.last_change = Some(change);
+            }
+        }
+        if self.mode == Mode::Normal {
+            self.clamp_cursor(editor);
+        }
+        if self.mode != Mode::Insert && !self.replaying {
+            editor.end_undo_step();
+        }
+    }
+
+    //Does what `command` stands for, `run` takes care of undo and `.` around it
+    fn execute(self: &mut Self, editor: &mut Editor, count: Option<usize>, command: Command) {
+        let times = count.unwrap_or(1);
+        match command {
+            Command::Move(motion) => {
+                let index = self.motion_target(editor, motion, count);
+                editor.move_to_index(index);
+            }
+            Command::Operate(operator, target) => self.operate_on(editor, operator, target, count),
+            Command::OperateSelection(operator) => {
+                let selection = match editor.selection() {
+                    Some(selection) => selection.range(),
+                    None => return self.leave_visual(editor),
+                };
+                let linewise = self.mode == Mode::VisualLine;
+                let end = editor.storage().next_char_boundary(selection.end);
+                self.leave_visual(editor);
+                self.operate(editor, operator, selection.start..end, linewise);
+            }
+            Command::Select(object) => {
+                let index = editor.cursor_index();
+                if let Some((range, linewise)) =
+                    object_range(editor.storage(), editor.word_chars(), index, object)
+                {
+                    if range.is_empty() {
+                        return;
+                    }
+
+                    let kind = if linewise {
+                        self.mode = Mode::VisualLine;
+                        SelectionKind::Lines
+                    } else {
+                        SelectionKind::Inclusive
+                    };
+                    let last = editor.storage().prev_char_boundary(range.end);
+                    editor.move_to_index(last);
+                    editor.set_selection(Some(range.start), kind);
+                }
+            }
+            Command::Action('x') => {
+                let index = editor.cursor_index();
+                let storage = editor.storage();
+                let end = line_end(storage, line_of(storage, index));
+                let to = (0..times).fold(index, |pos, _| min(storage.next_char_boundary(pos), end));
+                if to > index {
+                    self.operate(editor, Operator::Delete, index..to, false);
+                }
+            }
+            //The other end of the selection
+            Command::Action('o') if self.mode != Mode::Normal => {
+                if let Some(selection) = editor.selection() {
+                    editor.set_selection(Some(selection.head), self.selection_kind());
+                    editor.move_to_index(selection.anchor);
+                }
+            }
+            Command::Action('p') => paste(editor, false, times),
+            Command::Action('P') => paste(editor, true, times),
+            Command::Action(action @ ('o' | 'O')) => {
+                let index = editor.cursor_index();
+                let storage = editor.storage();
+                let line = line_of(storage, index);
+                if action == 'o' {
+                    let end = line_end(storage, line);
+                    editor.replace(end..end, "\n");
+                } else {
+                    let start = line_start(storage, line);
+                    editor.replace(start..start, "\n");
+                    editor.move_to_index(start);
+                }
+                self.mode = Mode::Insert;
+            }
+            Command::Action(action @ ('i' | 'a' | 'I' | 'A')) => {
+                let index = editor.cursor_index();
+                let storage = editor.storage();
+                let line = line_of(storage, index);
+                let target = match action {
+                    'a' => min(storage.next_char_boundary(index), line_end(storage, line)),
+                    'I' => line_start(storage, line),
+                    'A' => line_end(storage, line),
+                    _ => index,
+                };
+                editor.move_to_index(target);
+                self.mode = Mode::Insert;
+            }
+            Command::Action('u') => {
+                for _ in 0..times {
+                    editor.undo();
+                }
+            }
+            Command::Action('.') => self.repeat(editor, times),
+            Command::Action(action @ ('v' | 'V')) => {
+                let mode = if action == 'v' {
+                    Mode::Visual
+                } else {
+                    Mode::VisualLine
+                };
+                if self.mode == mode {
+                    return self.leave_visual(editor);
+                }
+
+                let anchor = match editor.selection() {
+                    Some(selection) if self.mode != Mode::Normal => selection.anchor,
+                    _ => editor.cursor_index(),
+                };
+                self.mode = mode;
+                editor.set_selection(Some(anchor), self.selection_kind());
+            }
+            Command::Action(_) => {}
+        }
+    }
+
+    //Runs the last change again, `times` times over
+    fn repeat(self: &mut Self, editor: &mut Editor, times: usize) {
+        let change = match &self.last_change {
+            Some(change) => change.clone(),
+            None => return,
+        };
+
+        self.replaying = true;
+        for _ in 0..times {
+            for ch in change.keys.chars() {
+                self.command_key(editor, KeyEvent::new(KeyCode::Char(ch), KeyModifiers::NONE));
+            }
+            if let Some(inserted) = &change.inserted {
+                for code in inserted {
+                    retype(editor, *code);
+                }
+                self.insert_key(editor, KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+            }
+        }
+        self.replaying = false;
+    }
+
+    fn operate_on(
+        self: &mut Self,
+        editor: &mut Editor,
+        operator: Operator,
+        target: Target,
+        count: Option<usize>,
+    ) {
+        let index = editor.cursor_index();
+        let times = count.unwrap_or(1);
+        match target {
+            Target::Lines => {
+                let storage = editor.storage();
+                let line = line_of(storage, index);
+                let last = min(line.saturating_add(times - 1), storage.line_count() - 1);
+                let end = line_start(storage, last);
+                self.operate(editor, operator, index..end, true);
+            }
+            Target::Object(object) => {
+                if let Some((range, linewise)) =
+                    object_range(editor.storage(), editor.word_chars(), index, object)
+                {
+                    self.operate(editor, operator, range, linewise);
+                }
+            }
+            Target::Motion(motion) => {
+                let target = self.motion_target(editor, motion, count);
+                let storage = editor.storage();
+                let word_chars = editor.word_chars();
+                let (from, mut to) = (min(index, target), index.max(target));
+                if motion.inclusive() {
+                    to = storage.next_char_boundary(to);
+                }
+
+                let on_word = storage
+                    .chars_at(index)
+                    .next()
+                    .is_some_and(|ch| !is_blank(word_chars.class(ch)));
+                if motion == Motion::WordStart && operator == Operator::Change && on_word {
+                    //`cw` changes up to the end of the word only, not the blanks after it
+                    let end = words::word_at(storage, index, word_chars).end;
+                    let last = (1..times).fold(storage.prev_char_boundary(end), |pos, _| {
+                        word_end(storage, word_chars, pos)
+                    });
+                    to = storage.next_char_boundary(last);
+                } else if motion == Motion::WordStart
+                    && line_of(storage, to) > line_of(storage, from)
+                {
+                    //A word motion landing on the first word of a line stops at the end of the
+                    //line before instead of taking the line break
+                    let line = line_of(storage, to);
+                    let start = line_start(storage, line);
+                    if storage.chars_in(start..to).all(char::is_whitespace) {
+                        to = line_end(storage, line - 1).max(from);
+                    }
+                }
+
+                self.operate(editor, operator, from..to, motion.linewise());
+            }
+        }
+    }
+
+    //Deletes, changes or yanks `range`, or all of the lines it touches when `linewise`
+    fn operate(
+        self: &mut Self,
+        editor: &mut Editor,
+        operator: Operator,
+        range: Range<usize>,
+        linewise: bool,
+    ) {
+        let storage = editor.storage();
+        if !linewise {
+            let text = storage.text_range(range.clone());
+            editor.registers().store(None, &text);
+            match operator {
+                Operator::Yank => editor.move_to_index(range.start),
+                Operator::Delete => editor.replace(range, ""),
+                Operator::Change => {
+                    editor.replace(range, "");
+                    self.mode = Mode::Insert;
+                }
+            }
+            return;
+        }
+
+        let first = line_of(storage, range.start);
+        let last = line_of(storage, range.end);
+        let start = line_start(storage, first);
+        let end = if last + 1 < storage.line_count() {
+            line_start(storage, last + 1)
+        } else {
+            storage.len()
+        };
+
+        //A change keeps the line break of the last line
+        let change_end = line_end(storage, last);
+        let mut text = storage.text_range(start..end);
+        let last_line = !text.ends_with('\n');
+        if last_line {
+            text.push('\n');
+        }
+        editor.registers().store(None, &text);
+
+        match operator {
+            Operator::Yank => editor.move_to_index(start),
+            Operator::Delete => {
+                //The last line takes the line break before it along
+                if last_line && start > 0 {
+                    editor.replace(start - 1..end, "");
+                    let storage = editor.storage();
+                    let line = line_of(storage, start - 1);
+                    editor.move_to_index(line_start(storage, line));
+                } else {
+                    editor.replace(start..end, "");
+                    editor.move_to_index(start);
+                }
+            }
+            Operator::Change => {
+                editor.replace(start..change_end, "");
+                self.mode = Mode::Insert;
+            }
+        }
+    }
+
+    fn motion_target(
+        self: &mut Self,
+        editor: &Editor,
+        motion: Motion,
+        count: Option<usize>,
+    ) -> usize {
+        let storage = editor.storage();
+        let word_chars = editor.word_chars();
+        let index = editor.cursor_index();
+        let line = line_of(storage, index);
+        let times = count.unwrap_or(1);
+
+        match motion {
+            Motion::Left => {
+                let start = line_start(storage, line);
+                (0..times).fold(index, |pos, _| storage.prev_char_boundary(pos).max(start))
+            }
+            Motion::Right => {
+                let end = line_end(storage, line);
+                (0..times).fold(index, |pos, _| min(storage.next_char_boundary(pos), end))
+            }
+            Motion::Down | Motion::Up => {
+                let column = *self.want_column.get_or_insert_with(|| {
+                    storage.chars_in(line_start(storage, line)..index).count()
+                });
+                let target = if motion == Motion::Down {
+                    min(line.saturating_add(times), storage.line_count() - 1)
+                } else {
+                    line.saturating_sub(times)
+                };
+
+                let start = line_start(storage, target);
+                let end = line_end(storage, target);
+                storage
+                    .chars_in(start..end)
+                    .take(column)
+                    .fold(start, |pos, ch| pos + ch.len_utf8())
+            }
+            Motion::WordStart => {
+                (0..times).fold(index, |pos, _| next_word_start(storage, word_chars, pos))
+            }
+            Motion::WordBack => {
+                (0..times).fold(index, |pos, _| prev_word_start(storage, word_chars, pos))
+            }
+            Motion::WordEnd => (0..times).fold(index, |pos, _| word_end(storage, word_chars, pos)),
+            Motion::LineStart => line_start(storage, line),
+            Motion::LineEnd => line_end(storage, line),
+            Motion::FirstLine | Motion::LastLine => {
+                let last = storage.line_count() - 1;
+                let target = match (count, motion) {
+                    (Some(count), _) => min(count.max(1) - 1, last),
+                    (None, Motion::FirstLine) => 0,
+                    (None, _) => last,
+                };
+                line_start(storage, target)
+            }
+        }
+    }
+
+    fn selection_kind(self: &Self) -> SelectionKind {
+        match self.mode {
+            Mode::VisualLine => SelectionKind::Lines,
+            _ => SelectionKind::Inclusive,
+        }
+    }
+
+    fn leave_visual(self: &mut Self, editor: &mut Editor) {
+        self.mode = Mode::Normal;
+        editor.set_selection(None, SelectionKind::Exclusive);
+        self.clamp_cursor(editor);
+    }
+
+    //Outside of insert mode the cursor sits on a char, never past the end of its line
+    fn clamp_cursor(self: &Self, editor: &mut Editor) {
+        let index = editor.cursor_index();
+        let storage = editor.storage();
+        let line = line_of(storage, index);
+        let end = line_end(storage, line);
+        if index >= end && end > line_start(storage, line) {
+            editor.move_to_index(storage.prev_char_boundary(end));
+        }
+    }
+
+    fn show_mode(self: &mut Self, editor: &mut Editor) {
+        let mode = match self.mode {
+            Mode::Normal => "",
+            Mode::Insert => "-- INSERT --",
+            Mode::Visual => "-- VISUAL --",
+            Mode::VisualLine => "-- VISUAL LINE --",
+        };
+        let shown = format!("{} {}", mode, self.pending).trim().to_string();
+        if shown != self.shown {
+            editor.set_status(shown.clone());
+            self.shown = shown;
+        }
+    }
+}
+
+//Keys typed in insert mode that `.` does again, text and moves
+fn repeatable(code: KeyCode) -> bool {
+    matches!(
+        code,
+        KeyCode::Char(_)
+            | KeyCode::Enter
+            | KeyCode::Tab
+            | KeyCode::Backspace
+            | KeyCode::Delete
+            | KeyCode::Left
+            | KeyCode::Right
+            | KeyCode::Up
+            | KeyCode::Down
+            | KeyCode::Home
+            | KeyCode::End
+    )
+}
+
+//Does what the usual key handling does with a key typed in insert mode
+fn retype(editor: &mut Editor, code: KeyCode) {
+    match code {
+        KeyCode::Char(ch) => editor.insert_ch(ch),
+        KeyCode::Enter => editor.insert_ch('\n'),
+        KeyCode::Tab => editor.insert_ch('\t'),
+        KeyCode::Backspace => editor.delete_ch(),
+        KeyCode::Delete => editor.delete_next_ch(),
+        KeyCode::Left => editor.move_cursor(Direction::LEFT, 1),
+        KeyCode::Right => editor.move_cursor(Direction::RIGHT, 1),
+        KeyCode::Up => editor.move_cursor(Direction::UP, 1),
+        KeyCode::Down => editor.move_cursor(Direction::DOWN, 1),
+        KeyCode::Home => editor.move_home(),
+        KeyCode::End => editor.move_end(),
+        _ => {}
+    }
+}
+
+//Arrows and the like stand in for the letters they match in vim
+fn key_char(code: KeyCode) -> Option<char> {
+    match code {
+        KeyCode::Char(ch) => Some(ch),
+        KeyCode::Left | KeyCode::Backspace => Some('h'),
+        KeyCode::Right => Some('l'),
+        KeyCode::Up => Some('k'),
+        KeyCode::Down => Some('j'),
+        KeyCode::Home => Some('0'),
+        KeyCode::End => Some('$'),
+        _ => None,
+    }
+}
+
+//`[count] motion`, `[count] operator [count] (motion | text object | operator)` or
+//`[count] action`. In visual mode operators need nothing after them, they work on the selection.
+fn parse(keys: &str, visual: bool) -> Parse<(Option<usize>, Command)> {
+    let mut chars = keys.chars().peekable();
+    let count = take_count(&mut chars);
+    let ch = match chars.next() {
+        Some(ch) => ch,
+        None => return Parse::Incomplete,
+    };
+
+    let operator = match ch {
+        'd' => Some(Operator::Delete),
+        'c' => Some(Operator::Change),
+        'y' => Some(Operator::Yank),
+        'x' if visual => Some(Operator::Delete),
+        _ => None,
+    };
+    if let Some(operator) = operator {
+        if visual {
+            return Parse::Done((count, Command::OperateSelection(operator)));
+        }
+
+        let inner_count = take_count(&mut chars);
+        let count = match (count, inner_count) {
+            (Some(outer), Some(inner)) => Some(min(outer.saturating_mul(inner), MAX_COUNT)),
+            (outer, inner) => outer.or(inner),
+        };
+        let target = match chars.next() {
+            None => return Parse::Incomplete,
+            Some(next) if next == ch => Target::Lines,
+            Some('i') => match chars.next().map(object) {
+                None => return Parse::Incomplete,
+                Some(Some(object)) => Target::Object(object),
+                Some(None) => return Parse::Invalid,
+            },
+            Some(next) => match motion(next, &mut chars) {
+                Parse::Done(motion) => Target::Motion(motion),
+                Parse::Incomplete => return Parse::Incomplete,
+                Parse::Invalid => return Parse::Invalid,
+            },
+        };
+        return Parse::Done((count, Command::Operate(operator, target)));
+    }
+
+    if ch == 'i' && visual {
+        return match chars.next().map(object) {
+            None => Parse::Incomplete,
+            Some(Some(object)) => Parse::Done((count, Command::Select(object))),
+            Some(None) => Parse::Invalid,
+        };
+    }
+
+    match motion(ch, &mut chars) {
+        Parse::Done(motion) => Parse::Done((count, Command::Move(motion))),
+        Parse::Incomplete => Parse::Incomplete,
+        Parse::Invalid if visual && "vVo".contains(ch) => Parse::Done((count, Command::Action(ch))),
+        Parse::Invalid if !visual && "xpPoOiaIAu.vV".contains(ch) => {
+            Parse::Done((count, Command::Action(ch)))
+        }
+        Parse::Invalid => Parse::Invalid,
+    }
+}
+
+fn take_count(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<usize> {
+    let mut count: Option<usize> = None;
+    while let Some(&ch) = chars.peek() {
+        let digit = match ch.to_digit(10) {
+            //A leading zero is the motion to the start of the line
+            Some(0) if count.is_none() => break,
+            Some(digit) => digit as usize,
+            None => break,
+        };
+        count = Some(min(count.unwrap_or(0) * 10 + digit, MAX_COUNT));
+        chars.next();
+    }
+
+    count
+}
+
+fn motion(ch: char, chars: &mut std::iter::Peekable<std::str::Chars>) -> Parse<Motion> {
+    let motion = match ch {
+        'h' => Motion::Left,
+        'l' => Motion::Right,
+        'j' => Motion::Down,
+        'k' => Motion::Up,
+        'w' => Motion::WordStart,
+        'b' => Motion::WordBack,
+        'e' => Motion::WordEnd,
+        '0' => Motion::LineStart,
+        '$' => Motion::LineEnd,
+        'G' => Motion::LastLine,
+        'g' => match chars.next() {
+            None => return Parse::Incomplete,
+            Some('g') => Motion::FirstLine,
+            Some(_) => return Parse::Invalid,
+        },
+        _ => return Parse::Invalid,
+    };
+
+    Parse::Done(motion)
+}
+
+fn object(ch: char) -> Option<Object> {
+    match ch {
+        'w' => Some(Object::Word),
+        '"' => Some(Object::Quotes),
+        '(' | ')' | 'b' => Some(Object::Parens),
+        'p' => Some(Object::Paragraph),
+        _ => None,
+    }
+}
+
+//Inserts the latest cut or copy `times` times over. Text ending in a line break is a whole line
+//and goes below the cursor line, or above it with `before`.
+fn paste(editor: &mut Editor, before: bool, times: usize) {
+    let text = match editor.registers().get(None) {
+        Some(text) => text.repeat(times),
+        None => return,
+    };
+    let index = editor.cursor_index();
+    let storage = editor.storage();
+    let line = line_of(storage, index);
+
+    if text.ends_with('\n') {
+        if before {
+            let start = line_start(storage, line);
+            editor.replace(start..start, &text);
+            editor.move_to_index(start);
+        } else if line + 1 < storage.line_count() {
+            let start = line_start(storage, line + 1);
+            editor.replace(start..start, &text);
+            editor.move_to_index(start);
+        } else {
+            //Below the last line, which has no line break to put it after
+            let end = storage.len();
+            editor.replace(end..end, &format!("\n{}", &text[..text.len() - 1]));
+            editor.move_to_index(end + 1);
+        }
+        return;
+    }
+
+    let at = if before || index >= line_end(storage, line) {
+        index
+    } else {
+        storage.next_char_boundary(index)
+    };
+    editor.replace(at..at, &text);
+    let last = editor.storage().prev_char_boundary(at + text.len());
+    editor.move_to_index(last);
+}
+
+fn is_blank(class: CharClass) -> bool {
+    matches!(class, CharClass::Space | CharClass::Newline)
+}
+
+//`w`: the start of the next word, or an empty line
+fn next_word_start(storage: &dyn TextStorage, word_chars: &WordChars, pos: usize) -> usize {
+    let mut chars = storage.chars_at(pos).peekable();
+    let mut pos = pos;
+    let class = match chars.next() {
+        Some(ch) => {
+            pos += ch.len_utf8();
+            word_chars.class(ch)
+        }
+        None => return pos,
+    };
+    if !is_blank(class) {
+        while let Some(&ch) = chars.peek() {
+            if word_chars.class(ch) != class {
+                break;
+            }
+            pos += ch.len_utf8();
+            chars.next();
+        }
+    }
+
+    let mut after_newline = class == CharClass::Newline;
+    while let Some(&ch) = chars.peek() {
+        match word_chars.class(ch) {
+            CharClass::Space => after_newline = false,
+            CharClass::Newline if after_newline => break,
+            CharClass::Newline => after_newline = true,
+            _ => break,
+        }
+        pos += ch.len_utf8();
+        chars.next();
+    }
+
+    pos
+}
+
+//`b`: the start of the word before `pos`, or an empty line
+fn prev_word_start(storage: &dyn TextStorage, word_chars: &WordChars, pos: usize) -> usize {
+    let mut chars = storage.chars_rev_at(pos).peekable();
+    let mut pos = pos;
+    while let Some(&ch) = chars.peek() {
+        if !is_blank(word_chars.class(ch)) {
+            break;
+        }
+        pos -= ch.len_utf8();
+        chars.next();
+        if ch == '\n' && chars.peek() == Some(&'\n') {
+            return pos;
+        }
+    }
+
+    let class = match chars.peek() {
+        Some(&ch) => word_chars.class(ch),
+        None => return pos,
+    };
+    while let Some(&ch) = chars.peek() {
+        if word_chars.class(ch) != class {
+            break;
+        }
+        pos -= ch.len_utf8();
+        chars.next();
+    }
+
+    pos
+}
+
+//`e`: the last char of the word after `pos`
+fn word_end(storage: &dyn TextStorage, word_chars: &WordChars, pos: usize) -> usize {
+    let mut chars = storage.chars_at(pos).peekable();
+    let mut next = match chars.next() {
+        Some(ch) => pos + ch.len_utf8(),
+        None => return pos,
+    };
+    while let Some(&ch) = chars.peek() {
+        if !is_blank(word_chars.class(ch)) {
+            break;
+        }
+        next += ch.len_utf8();
+        chars.next();
+    }
+
+    let class = match chars.peek() {
+        Some(&ch) => word_chars.class(ch),
+        None => return pos,
+    };
+    let mut last = next;
+    while let Some(&ch) = chars.peek() {
+        if word_chars.class(ch) != class {
+            break;
+        }
+        last = next;
+        next += ch.len_utf8();
+        chars.next();
+    }
+
+    last
+}
+
+//What a text object covers around `pos`, and whether it is made of whole lines
+fn object_range(
+    storage: &dyn TextStorage,
+    word_chars: &WordChars,
+    pos: usize,
+    object: Object,
+) -> Option<(Range<usize>, bool)> {
+    match object {
+        Object::Word => Some((words::word_at(storage, pos, word_chars), false)),
+        Object::Quotes => {
+            let line = line_of(storage, pos);
+            let start = line_start(storage, line);
+            let mut quotes = Vec::new();
+            let mut index = start;
+            for ch in storage.chars_in(start..line_end(storage, line)) {
+                if ch == '"' {
+                    quotes.push(index);
+                }
+                index += ch.len_utf8();
+            }
+
+            //Quotes pair up from the start of the line, the first pair around or after the
+            //cursor counts
+            quotes
+                .chunks_exact(2)
+                .find(|pair| pos <= pair[1])
+                .map(|pair| (pair[0] + 1..pair[1], false))
+        }
+        Object::Parens => {
+            let mut depth = 0;
+            let mut open = None;
+            if storage.chars_at(pos).next() == Some('(') {
+                open = Some(pos);
+            } else {
+                let mut index = pos;
+                for ch in storage.chars_rev_at(pos) {
+                    index -= ch.len_utf8();
+                    match ch {
+                        ')' => depth += 1,
+                        '(' if depth == 0 => {
+                            open = Some(index);
+                            break;
+                        }
+                        '(' => depth -= 1,
+                        _ => {}
+                    }
+                }
+            }
+
+            let open = open?;
+            let mut depth = 0;
+            let mut index = open + 1;
+            for ch in storage.chars_at(open + 1) {
+                match ch {
+                    '(' => depth += 1,
+                    ')' if depth == 0 => return Some((open + 1..index, false)),
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                index += ch.len_utf8();
+            }
+            None
+        }
+        Object::Paragraph => {
+            let blank = |line: usize| {
+                storage
+                    .chars_in(line_start(storage, line)..line_end(storage, line))
+                    .all(char::is_whitespace)
+            };
+            let line = line_of(storage, pos);
+            let kind = blank(line);
+            let mut first = line;
+            while first > 0 && blank(first - 1) == kind {
+                first -= 1;
+            }
+            let mut last = line;
+            while last + 1 < storage.line_count() && blank(last + 1) == kind {
+                last += 1;
+            }
+
+            Some((line_start(storage, first)..line_start(storage, last), true))
+        }
+    }
+}
+
+fn line_of(storage: &dyn TextStorage, pos: usize) -> usize {
+    storage.byte_to_line(pos)
+}
+
+fn line_start(storage: &dyn TextStorage, line: usize) -> usize {
+    storage.line_to_byte(line)
+}
+
+//Where `line` ends, right before its line break
+fn line_end(storage: &dyn TextStorage, line: usize) -> usize {
+    if line + 1 < storage.line_count() {
+        storage.line_to_byte(line + 1) - 1
+    } else {
+        storage.len()
+    }
+}
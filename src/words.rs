@@ -0,0 +1,126 @@
+use std::ops::Range;
+
+use crate::storage::TextStorage;
+
+//What a char counts as when moving by words
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CharClass {
+    Space,
+    Newline,
+    Word,
+    Punctuation,
+}
+
+//Which chars make up words. Letters and digits of any script always do, `extra` adds more,
+//`_` by default. Runs of any other non-blank chars are words of their own, so `foo.bar()`
+//is `foo`, `.`, `bar` and `()`.
+#[derive(Clone)]
+pub struct WordChars {
+    extra: String,
+}
+
+impl WordChars {
+    pub fn new(extra: &str) -> Self {
+        WordChars {
+            extra: String::from(extra),
+        }
+    }
+
+    pub fn class(self: &Self, ch: char) -> CharClass {
+        if ch == '\n' {
+            CharClass::Newline
+        } else if ch.is_whitespace() {
+            CharClass::Space
+        } else if ch.is_alphanumeric() || self.extra.contains(ch) {
+            CharClass::Word
+        } else {
+            CharClass::Punctuation
+        }
+    }
+}
+
+impl Default for WordChars {
+    fn default() -> Self {
+        WordChars::new("_")
+    }
+}
+
+//Where the word at or after `pos` ends. Blanks before it are skipped, but not past the end of
+//the line, a line end is a stop of its own.
+pub fn word_end(storage: &dyn TextStorage, pos: usize, word_chars: &WordChars) -> usize {
+    skip_word(storage.chars_at(pos), pos, word_chars, |pos, ch| pos + ch.len_utf8())
+}
+
+//Where the word at or before `pos` starts, mirroring `word_end`
+pub fn word_start(storage: &dyn TextStorage, pos: usize, word_chars: &WordChars) -> usize {
+    skip_word(storage.chars_rev_at(pos), pos, word_chars, |pos, ch| pos - ch.len_utf8())
+}
+
+//The run of chars of the same class as the one at `pos`, a line break is a run of its own
+pub fn word_at(storage: &dyn TextStorage, pos: usize, word_chars: &WordChars) -> Range<usize> {
+    let class = match storage.chars_at(pos).next() {
+        Some(ch) => word_chars.class(ch),
+        None => return pos..pos,
+    };
+    if class == CharClass::Newline {
+        return pos..pos + 1;
+    }
+
+    let same_class = |ch: &char| word_chars.class(*ch) == class;
+    let start = pos
+        - storage
+            .chars_rev_at(pos)
+            .take_while(same_class)
+            .map(|ch| ch.len_utf8())
+            .sum::<usize>();
+    let end = pos
+        + storage
+            .chars_at(pos)
+            .take_while(same_class)
+            .map(|ch| ch.len_utf8())
+            .sum::<usize>();
+
+    start..end
+}
+
+fn skip_word(
+    chars: impl Iterator<Item = char>,
+    mut pos: usize,
+    word_chars: &WordChars,
+    step: impl Fn(usize, char) -> usize,
+) -> usize {
+    let mut chars = chars.peekable();
+    let mut skipped_space = false;
+    while let Some(&ch) = chars.peek() {
+        if word_chars.class(ch) != CharClass::Space {
+            break;
+        }
+        pos = step(pos, ch);
+        skipped_space = true;
+        chars.next();
+    }
+
+    let class = match chars.next() {
+        Some(ch) => {
+            let class = word_chars.class(ch);
+            if class == CharClass::Newline && skipped_space {
+                return pos;
+            }
+            pos = step(pos, ch);
+            class
+        }
+        None => return pos,
+    };
+    if class == CharClass::Newline {
+        return pos;
+    }
+
+    for ch in chars {
+        if word_chars.class(ch) != class {
+            break;
+        }
+        pos = step(pos, ch);
+    }
+
+    pos
+}